@@ -1,21 +1,28 @@
+/// JSON-RPC リクエスト ID
+export type RpcId = string | number | null;
+
 /// RPC Request
 export interface RpcRequest {
+  jsonrpc: "2.0";
   method: string;
-  params: any[];
-  param_types: string[];
-  id: number;
+  params?: any[] | Record<string, any>;
+  param_types?: string[];
+  /// 省略すると通知として扱われ、レスポンスは返らない
+  id?: RpcId;
 }
 
 /// RPC エラー
 export interface RpcError {
   code: number;
   message: string;
+  data?: any;
 }
 
 /// RPC レスポンス
 export interface RpcResponse {
-  result?: number;
+  jsonrpc: "2.0";
+  result?: any;
   result_type?: string;
   error?: RpcError;
-  id: number;
+  id: RpcId;
 }
@@ -0,0 +1,369 @@
+//! `server` クレートの手続きマクロ
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    Attribute, FnArg, GenericArgument, ImplItem, ImplItemFn, ItemImpl, LitStr, Pat, Path,
+    PathArguments, ReturnType, Type, parse_macro_input, spanned::Spanned,
+};
+
+/// impl ブロックの関数を RPC メソッドとして公開する
+///
+/// ```ignore
+/// pub struct Math;
+///
+/// #[rpc_service]
+/// impl Math {
+///     fn floor(&self, x: f64) -> i64 { .. }
+///     async fn sort(&self, #[rpc(name = "strArr")] str_arr: Vec<String>) -> Vec<String> { .. }
+/// }
+/// ```
+///
+/// 次のものを生成する
+///
+/// - `RpcService` の実装（メソッド名の一覧とメソッドテーブル）
+/// - 同じメソッドを呼び出す型付きのクライアント `MathClient`
+///
+/// メソッド名や引数名は `#[rpc(name = "..")]` で変えられる。`#[rpc(skip)]` を付けた関数は公開しない。
+/// クライアントの `new`・`mounted`・`into_inner` と同じ名前の関数は、名前を変えて `#[rpc(name = "..")]` で公開する。
+/// `server` クレートを別名で使っている場合は `#[rpc_service(crate = path)]` でパスを指定する
+#[proc_macro_attribute]
+pub fn rpc_service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut krate: Path = syn::parse_quote!(::server);
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("crate") {
+            krate = meta.value()?.parse()?;
+            Ok(())
+        } else {
+            Err(meta.error("expected `crate = path`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let item = parse_macro_input!(item as ItemImpl);
+    match expand(item, &krate) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// `#[rpc(..)]` で指定できる項目
+#[derive(Default)]
+struct RpcAttr {
+    name: Option<String>,
+    skip: bool,
+}
+
+/// `#[rpc(..)]` を読み取り、`attrs` から取り除く
+fn take_rpc_attr(attrs: &mut Vec<Attribute>) -> syn::Result<RpcAttr> {
+    let mut parsed = RpcAttr::default();
+    let mut error = None;
+    attrs.retain(|attr| {
+        if !attr.path().is_ident("rpc") {
+            return true;
+        }
+        let result = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                parsed.name = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else if meta.path.is_ident("skip") {
+                parsed.skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"..\"` or `skip`"))
+            }
+        });
+        if let Err(e) = result {
+            error.get_or_insert(e);
+        }
+        false
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(parsed),
+    }
+}
+
+/// 生成するクライアントが持つ関数。RPC メソッドに同じ名前は使えない
+const CLIENT_FUNCTIONS: &[&str] = &["new", "mounted", "into_inner"];
+
+/// 公開する1つのメソッド
+struct Method {
+    /// RPC のメソッド名
+    name: String,
+    /// エラーを示す位置（関数名）
+    span: Span,
+    ident: syn::Ident,
+    has_receiver: bool,
+    is_async: bool,
+    /// 引数の RPC 上の名前と型
+    args: Vec<(String, Type)>,
+    /// クライアントが受け取る値の型
+    output: Type,
+}
+
+fn parse_method(item: &mut ImplItemFn) -> syn::Result<Option<Method>> {
+    let attr = take_rpc_attr(&mut item.attrs)?;
+    if attr.skip {
+        return Ok(None);
+    }
+
+    let sig = &mut item.sig;
+    if CLIENT_FUNCTIONS.iter().any(|name| sig.ident == name) {
+        return Err(syn::Error::new(
+            sig.ident.span(),
+            format!(
+                "`{0}` conflicts with `{0}` of the generated client; rename the function and \
+                 keep the RPC name with #[rpc(name = \"{0}\")]",
+                sig.ident
+            ),
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "RPC methods cannot be generic",
+        ));
+    }
+
+    let mut has_receiver = false;
+    let mut args = Vec::new();
+    for input in sig.inputs.iter_mut() {
+        match input {
+            FnArg::Receiver(receiver) => {
+                if receiver.reference.is_none() || receiver.mutability.is_some() {
+                    return Err(syn::Error::new(
+                        receiver.span(),
+                        "RPC methods must take `&self`",
+                    ));
+                }
+                has_receiver = true;
+            }
+            FnArg::Typed(arg) => {
+                let attr = take_rpc_attr(&mut arg.attrs)?;
+                let name = match (attr.name, &*arg.pat) {
+                    (Some(name), _) => name,
+                    (None, Pat::Ident(pat)) => pat.ident.to_string(),
+                    (None, pat) => {
+                        return Err(syn::Error::new(
+                            pat.span(),
+                            "add #[rpc(name = \"..\")] to name this argument",
+                        ));
+                    }
+                };
+                args.push((name, (*arg.ty).clone()));
+            }
+        }
+    }
+
+    Ok(Some(Method {
+        name: attr.name.unwrap_or_else(|| sig.ident.to_string()),
+        span: sig.ident.span(),
+        ident: sig.ident.clone(),
+        has_receiver,
+        is_async: sig.asyncness.is_some(),
+        args,
+        output: client_output(&sig.output),
+    }))
+}
+
+/// `Result<T, E>` を返す関数は `T`、それ以外は戻り値の型そのものをクライアントの戻り値にする
+fn client_output(output: &ReturnType) -> Type {
+    let ReturnType::Type(_, ty) = output else {
+        return syn::parse_quote!(());
+    };
+    if let Type::Path(path) = &**ty
+        && let Some(last) = path.path.segments.last()
+        && last.ident == "Result"
+        && let PathArguments::AngleBracketed(generics) = &last.arguments
+        && let Some(GenericArgument::Type(ok)) = generics.args.first()
+    {
+        return ok.clone();
+    }
+    (**ty).clone()
+}
+
+fn expand(mut item: ItemImpl, krate: &Path) -> syn::Result<TokenStream2> {
+    if item.trait_.is_some() {
+        return Err(syn::Error::new(
+            item.span(),
+            "#[rpc_service] must be placed on an inherent impl block",
+        ));
+    }
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "#[rpc_service] does not support generic types",
+        ));
+    }
+
+    let mut methods = Vec::new();
+    for impl_item in item.items.iter_mut() {
+        if let ImplItem::Fn(function) = impl_item
+            && let Some(method) = parse_method(function)?
+        {
+            methods.push(method);
+        }
+    }
+
+    // 同じ名前のメソッドは片方が登録されなくなるので、ここで誤りにする
+    for (index, method) in methods.iter().enumerate() {
+        if methods[..index]
+            .iter()
+            .any(|other| other.name == method.name)
+        {
+            return Err(syn::Error::new(
+                method.span,
+                format!("duplicate RPC method name `{}`", method.name),
+            ));
+        }
+    }
+
+    let self_ty = &item.self_ty;
+    let Type::Path(self_path) = &**self_ty else {
+        return Err(syn::Error::new(
+            self_ty.span(),
+            "#[rpc_service] must be placed on a named type",
+        ));
+    };
+    let self_ident = &self_path
+        .path
+        .segments
+        .last()
+        .expect("type path has at least one segment")
+        .ident;
+    let client_ident = format_ident!("{}Client", self_ident);
+
+    let names = methods.iter().map(|method| &method.name);
+    let registrations = methods.iter().map(|method| registration(method, krate));
+    let client_methods = methods.iter().map(|method| client_method(method, krate));
+
+    let client_doc = format!(
+        "[`{}`] のメソッドを呼び出す型付きのクライアント",
+        self_ident
+    );
+
+    Ok(quote! {
+        #item
+
+        impl #krate::service::RpcService for #self_ty {
+            const METHODS: &'static [&'static str] = &[#(#names),*];
+
+            fn method_table(self) -> #krate::MethodTable {
+                #[allow(unused_variables)]
+                let this = ::std::sync::Arc::new(self);
+                let mut table = #krate::MethodTable::new();
+                #(#registrations)*
+                table
+            }
+        }
+
+        #[doc = #client_doc]
+        pub struct #client_ident {
+            client: #krate::client::RpcClient,
+            prefix: ::std::string::String,
+        }
+
+        impl #client_ident {
+            pub fn new(client: #krate::client::RpcClient) -> Self {
+                Self::mounted(client, "")
+            }
+
+            /// 名前空間 `prefix` に登録されたサービスを呼び出す
+            pub fn mounted(
+                client: #krate::client::RpcClient,
+                prefix: impl ::std::convert::Into<::std::string::String>,
+            ) -> Self {
+                #client_ident {
+                    client,
+                    prefix: prefix.into(),
+                }
+            }
+
+            /// 元のクライアントを取り出す
+            pub fn into_inner(self) -> #krate::client::RpcClient {
+                self.client
+            }
+
+            #(#client_methods)*
+        }
+    })
+}
+
+/// メソッドテーブルへの登録
+fn registration(method: &Method, krate: &Path) -> TokenStream2 {
+    let name = &method.name;
+    let ident = &method.ident;
+    let param_names = method.args.iter().map(|(name, _)| name);
+    let arg_idents = (0..method.args.len())
+        .map(|index| format_ident!("__arg{}", index))
+        .collect::<Vec<_>>();
+    let arg_types = method.args.iter().map(|(_, ty)| ty);
+
+    let call = if method.has_receiver {
+        quote!(this.#ident(#(#arg_idents),*))
+    } else {
+        quote!(Self::#ident(#(#arg_idents),*))
+    };
+    let (marker, body) = if method.is_async {
+        let body = if method.has_receiver {
+            quote! {
+                let this = ::std::sync::Arc::clone(&this);
+                async move { #call.await }
+            }
+        } else {
+            quote!(#call)
+        };
+        (quote!(#krate::typed::AsyncHandler), body)
+    } else {
+        (quote!(#krate::typed::SyncHandler), call)
+    };
+    // `&self` を取るメソッドだけがサービスを共有する
+    let capture = method
+        .has_receiver
+        .then(|| quote!(let this = ::std::sync::Arc::clone(&this);));
+
+    quote! {
+        {
+            #capture
+            #krate::service::insert(
+                &mut table,
+                #name.to_string(),
+                #krate::RpcMethod::typed::<_, #marker, _>(
+                    [#(#param_names),*],
+                    move |#(#arg_idents: #arg_types),*| { #body },
+                ),
+            );
+        }
+    }
+}
+
+/// クライアントのメソッド。引数は位置指定で送る
+fn client_method(method: &Method, krate: &Path) -> TokenStream2 {
+    let name = &method.name;
+    let ident = &method.ident;
+    let output = &method.output;
+    let arg_idents = (0..method.args.len())
+        .map(|index| format_ident!("__arg{}", index))
+        .collect::<Vec<_>>();
+    let arg_types = method.args.iter().map(|(_, ty)| ty);
+    let doc = format!("`{}` を呼び出す", name);
+
+    quote! {
+        #[doc = #doc]
+        pub async fn #ident(
+            &self,
+            #(#arg_idents: #arg_types),*
+        ) -> ::std::result::Result<#output, #krate::client::ClientError> {
+            let params = ::std::vec![
+                #(#krate::__private::serde_json::to_value(&#arg_idents)?),*
+            ];
+            self.client
+                .call(
+                    &#krate::service::qualified_name(&self.prefix, #name),
+                    #krate::Params::ByPosition(params),
+                )
+                .await
+        }
+    }
+}
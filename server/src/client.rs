@@ -0,0 +1,160 @@
+//! Unix Domain Socket / TCP で JSON-RPC を呼び出すクライアント
+//!
+//! メッセージは改行区切りの JSON でやり取りする
+
+use std::{
+    fmt, io,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, UnixStream},
+    sync::Mutex,
+};
+
+use crate::{
+    rpc::{Params, RpcError, RpcId, RpcMessage, RpcNotification, RpcRequest, Version},
+    transport::{BoxReader, BoxWriter, ListenAddr},
+};
+
+/// [`RpcClient`] の呼び出しで起きるエラー
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// 引数や結果を JSON に変換できない
+    Json(serde_json::Error),
+    /// サーバーが返したエラー
+    Rpc(RpcError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "I/O error: {}", e),
+            ClientError::Json(e) => write!(f, "JSON error: {}", e),
+            ClientError::Rpc(e) => write!(f, "RPC error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::Json(e) => Some(e),
+            ClientError::Rpc(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Json(e)
+    }
+}
+
+impl From<RpcError> for ClientError {
+    fn from(e: RpcError) -> Self {
+        ClientError::Rpc(e)
+    }
+}
+
+/// 1つの接続でリクエストを1件ずつ送るクライアント
+pub struct RpcClient {
+    conn: Mutex<(BufReader<BoxReader>, BoxWriter)>,
+    next_id: AtomicI64,
+}
+
+impl RpcClient {
+    /// `unix://` または `tcp://` のアドレスに接続する
+    pub async fn connect(addr: &ListenAddr) -> io::Result<Self> {
+        let (reader, writer): (BoxReader, BoxWriter) = match addr {
+            ListenAddr::Unix(path) => {
+                let (read_half, write_half) = UnixStream::connect(path).await?.into_split();
+                (Box::new(read_half), Box::new(write_half))
+            }
+            ListenAddr::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(true)?;
+                let (read_half, write_half) = stream.into_split();
+                (Box::new(read_half), Box::new(write_half))
+            }
+            #[cfg(any(feature = "http", feature = "websocket"))]
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("cannot connect to {} (expected unix:// or tcp://)", addr),
+                ));
+            }
+        };
+        Ok(RpcClient {
+            conn: Mutex::new((BufReader::new(reader), writer)),
+            next_id: AtomicI64::new(1),
+        })
+    }
+
+    /// メソッドを呼び出し、結果を `T` として受け取る
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<T, ClientError> {
+        let id = RpcId::Number(self.next_id.fetch_add(1, Ordering::Relaxed).into());
+        let request = RpcRequest {
+            jsonrpc: Version,
+            method: method.to_string(),
+            params,
+            param_types: None,
+            id: Some(id.clone()),
+        };
+
+        let mut conn = self.conn.lock().await;
+        let (reader, writer) = &mut *conn;
+        send(writer, &request).await?;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            // 別のリクエストへの返信は読み飛ばす
+            match serde_json::from_str::<RpcMessage>(&line)? {
+                RpcMessage::Response(response) if response.id == id => {
+                    return Ok(serde_json::from_value(response.result)?);
+                }
+                RpcMessage::Error(response) if response.id == id => {
+                    return Err(ClientError::Rpc(response.error));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 通知を送る。返信は待たない
+    pub async fn notify(&self, method: &str, params: Value) -> Result<(), ClientError> {
+        let notification = RpcNotification {
+            jsonrpc: Version,
+            method: method.to_string(),
+            params,
+        };
+        let mut conn = self.conn.lock().await;
+        send(&mut conn.1, &notification).await
+    }
+}
+
+async fn send(writer: &mut BoxWriter, message: &impl serde::Serialize) -> Result<(), ClientError> {
+    let mut json = serde_json::to_vec(message)?;
+    json.push(b'\n');
+    writer.write_all(&json).await?;
+    Ok(())
+}
@@ -0,0 +1,465 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore, mpsc},
+    task::{AbortHandle, JoinSet},
+};
+
+use crate::{
+    method::{MethodTable, RpcMethod},
+    rpc::{
+        Params, RpcError, RpcErrorResponse, RpcId, RpcMessage, RpcReply, RpcRequest, RpcResponse,
+        Version,
+    },
+};
+
+/// リクエストを中断する通知のメソッド名
+const CANCEL_REQUEST: &str = "$/cancelRequest";
+
+/// 1つの接続で実行中のハンドラ。`$/cancelRequest` で `id` を指定して中断できる
+#[derive(Clone, Default)]
+pub(crate) struct Running(Arc<Mutex<HashMap<RpcId, Slot>>>);
+
+enum Slot {
+    /// 列から取り出したがハンドラはまだ始まっていない。`cancelled` なら始めたところで中断する
+    Starting {
+        cancelled: bool,
+    },
+    Running(AbortHandle),
+}
+
+impl Running {
+    /// これから処理するリクエストを登録し、ハンドラが始まる前に届いた中断も受け付ける
+    fn reserve(&self, id: RpcId) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert(Slot::Starting { cancelled: false });
+    }
+
+    /// 処理が終わっても始まらなかったリクエストの登録を取り除く
+    fn release(&self, id: &RpcId) {
+        let mut running = self.0.lock().unwrap();
+        if matches!(running.get(id), Some(Slot::Starting { .. })) {
+            running.remove(id);
+        }
+    }
+
+    fn insert(&self, id: RpcId, handle: AbortHandle) {
+        let mut running = self.0.lock().unwrap();
+        if let Some(Slot::Starting { cancelled: true }) = running.get(&id) {
+            running.remove(&id);
+            handle.abort();
+        } else {
+            running.insert(id, Slot::Running(handle));
+        }
+    }
+
+    /// 終わったハンドラを取り除く。同じ `id` で後から始まったものは残す
+    fn remove(&self, id: &RpcId, handle: &AbortHandle) {
+        let mut running = self.0.lock().unwrap();
+        if matches!(running.get(id), Some(Slot::Running(h)) if h.id() == handle.id()) {
+            running.remove(id);
+        }
+    }
+
+    fn cancel(&self, id: &RpcId) -> bool {
+        let mut running = self.0.lock().unwrap();
+        match running.get_mut(id) {
+            Some(Slot::Starting { cancelled }) => {
+                *cancelled = true;
+                true
+            }
+            Some(Slot::Running(handle)) => {
+                handle.abort();
+                running.remove(id);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// 単体リクエストのメソッド名と `id` だけを読むための形
+#[derive(Deserialize)]
+struct Peek<'a> {
+    #[serde(borrow)]
+    method: Cow<'a, str>,
+    #[serde(default)]
+    id: Option<RpcId>,
+}
+
+/// `$/cancelRequest` のパラメータ
+#[derive(Deserialize)]
+struct CancelParams {
+    id: RpcId,
+}
+
+/// 受信した1メッセージ（単体リクエストまたはバッチ）を処理し、返すべき返信を作る
+///
+/// 通知だけの場合は `None` を返す
+pub(crate) async fn handle_message(
+    message: &str,
+    method_table: &Arc<MethodTable>,
+    running: &Running,
+) -> Option<RpcReply> {
+    // JSONのパース処理
+    let value = match serde_json::from_str::<Value>(message) {
+        Ok(value) => value,
+        Err(e) => {
+            log::warn!("エラー: {}", e);
+            let error = RpcError::parse_error().with_data(e.to_string());
+            let response = RpcErrorResponse::new(error, RpcId::Null);
+            return Some(RpcReply::Single(RpcMessage::Error(response)));
+        }
+    };
+
+    let Value::Array(items) = value else {
+        return handle_request(value, method_table, running)
+            .await
+            .map(RpcReply::Single);
+    };
+
+    // 空のバッチはそれ自体が不正なリクエスト
+    if items.is_empty() {
+        let error = RpcError::invalid_request().with_data("empty batch");
+        let response = RpcErrorResponse::new(error, RpcId::Null);
+        return Some(RpcReply::Single(RpcMessage::Error(response)));
+    }
+
+    // バッチの各要素は別タスクで並行に処理する
+    let handles = items
+        .into_iter()
+        .map(|item| {
+            let method_table = Arc::clone(method_table);
+            let running = running.clone();
+            tokio::spawn(async move { handle_request(item, &method_table, &running).await })
+        })
+        .collect::<Vec<_>>();
+
+    let mut responses = Vec::new();
+    for handle in handles {
+        match handle.await {
+            Ok(Some(response)) => responses.push(response),
+            Ok(None) => {}
+            Err(e) => log::warn!("バッチ要素の処理に失敗: {}", e),
+        }
+    }
+
+    // 通知だけのバッチには何も返さない
+    if responses.is_empty() {
+        None
+    } else {
+        Some(RpcReply::Batch(responses))
+    }
+}
+
+/// 1つの接続で並行に処理中のメッセージと、処理の空きを待っているメッセージ
+pub(crate) struct InFlight {
+    tasks: JoinSet<()>,
+    permits: Arc<Semaphore>,
+    running: Running,
+    queued: VecDeque<Queued>,
+    max_queued: usize,
+}
+
+/// 処理の空きを待っているメッセージ
+struct Queued {
+    message: String,
+    /// 単体リクエストの `id`。`$/cancelRequest` で列から取り除くために使う
+    id: Option<RpcId>,
+}
+
+impl InFlight {
+    /// 同時に処理するメッセージを `max_concurrent` 件までにする。空きを待つメッセージも同じ件数（とさらに1件）まで溜める
+    pub(crate) fn new(max_concurrent: usize) -> Self {
+        InFlight {
+            tasks: JoinSet::new(),
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            running: Running::default(),
+            queued: VecDeque::new(),
+            max_queued: max_concurrent,
+        }
+    }
+
+    /// 空きを待つメッセージが上限を超えているかどうか。超えている間は接続から読み込まない
+    ///
+    /// 上限に達した後も1件だけは読んで列に加えるので、列が埋まってから届いた
+    /// `$/cancelRequest` も処理できる
+    pub(crate) fn is_full(&self) -> bool {
+        self.queued.len() > self.max_queued
+    }
+
+    /// 空きを待つメッセージがあるかどうか
+    pub(crate) fn has_queued(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    /// メッセージを空きを待つ列に加える
+    ///
+    /// `$/cancelRequest` は処理の枠が埋まっていても届くよう、列に加えずにその場で処理する。
+    /// 列で待っているリクエストが対象の場合は、列から取り除いて中断のエラーを返す。
+    /// すぐに送るべき返信を JSON で返す
+    pub(crate) fn push(&mut self, message: String) -> Vec<String> {
+        let peeked = serde_json::from_str::<Peek>(&message).ok();
+        if peeked
+            .as_ref()
+            .is_some_and(|peek| peek.method == CANCEL_REQUEST)
+            && let Ok(request) = serde_json::from_str::<RpcRequest>(&message)
+        {
+            let mut replies = Vec::new();
+            let reply = cancel_request(request, |id| {
+                if self.running.cancel(id) {
+                    return true;
+                }
+                let Some(index) = self.queued.iter().position(|q| q.id.as_ref() == Some(id)) else {
+                    return false;
+                };
+                self.queued.remove(index);
+                let error = RpcError::request_cancelled();
+                replies.push(RpcMessage::Error(RpcErrorResponse::new(error, id.clone())));
+                true
+            });
+            replies.extend(reply);
+            return replies.iter().filter_map(to_json).collect();
+        }
+
+        let id = peeked.and_then(|peek| peek.id);
+        self.queued.push_back(Queued { message, id });
+        Vec::new()
+    }
+
+    /// 処理の空きができるまで待つ
+    ///
+    /// `self` を借用しないので、読み込みループではシャットダウンや通知と一緒に `select!` で待てる
+    pub(crate) fn acquire(&self) -> impl Future<Output = Option<OwnedSemaphorePermit>> + use<> {
+        let permits = Arc::clone(&self.permits);
+        async move { permits.acquire_owned().await.ok() }
+    }
+
+    /// 待っている先頭のメッセージを、`permit` の空きを使って別タスクで処理する
+    ///
+    /// 返信は JSON にして `wrap` で包み、終わった順に `replies` へ送る。
+    /// クライアントは `id` でリクエストと対応付ける
+    pub(crate) fn spawn_next<T: Send + 'static>(
+        &mut self,
+        permit: OwnedSemaphorePermit,
+        methods: &Arc<MethodTable>,
+        replies: &mpsc::Sender<T>,
+        wrap: impl FnOnce(String) -> T + Send + 'static,
+    ) {
+        // 終わったタスクを片付ける
+        while self.tasks.try_join_next().is_some() {}
+
+        let Some(Queued { message, id }) = self.queued.pop_front() else {
+            return;
+        };
+        // タスクが始まるまでの間に届いた `$/cancelRequest` も取りこぼさない
+        if let Some(id) = &id {
+            self.running.reserve(id.clone());
+        }
+        let methods = Arc::clone(methods);
+        let replies = replies.clone();
+        let running = self.running.clone();
+        self.tasks.spawn(async move {
+            let _permit = permit;
+            let reply = handle_message(&message, &methods, &running).await;
+            if let Some(id) = &id {
+                running.release(id);
+            }
+            // 通知の場合は何も返さない
+            let Some(reply) = reply else {
+                return;
+            };
+            match serde_json::to_string(&reply) {
+                Ok(json_response) => {
+                    let _ = replies.send(wrap(json_response)).await;
+                }
+                Err(e) => {
+                    log::warn!("Error converting response to JSON: {}", e);
+                }
+            }
+        });
+    }
+
+    /// 待っているメッセージも処理し、すべて終わるまで待つ
+    pub(crate) async fn finish<T: Send + 'static>(
+        mut self,
+        methods: &Arc<MethodTable>,
+        replies: &mpsc::Sender<T>,
+        wrap: impl FnOnce(String) -> T + Clone + Send + 'static,
+    ) {
+        while self.has_queued() {
+            let Some(permit) = self.acquire().await else {
+                break;
+            };
+            self.spawn_next(permit, methods, replies, wrap.clone());
+        }
+        while let Some(result) = self.tasks.join_next().await {
+            if let Err(e) = result {
+                log::warn!("メッセージの処理に失敗: {}", e);
+            }
+        }
+    }
+}
+
+/// 返信を JSON にする
+fn to_json(reply: &RpcMessage) -> Option<String> {
+    match serde_json::to_string(reply) {
+        Ok(json_response) => Some(json_response),
+        Err(e) => {
+            log::warn!("Error converting response to JSON: {}", e);
+            None
+        }
+    }
+}
+
+/// 上限を超えるメッセージへの返信。本文を読んでいないので `id` は null になる
+pub(crate) fn too_large_reply(max_size: usize) -> RpcReply {
+    let error = RpcError::invalid_request().with_data(format!(
+        "message exceeds the maximum size of {} bytes",
+        max_size
+    ));
+    RpcReply::Single(RpcMessage::Error(RpcErrorResponse::new(error, RpcId::Null)))
+}
+
+/// 1つのリクエストをメソッドテーブルで処理する
+///
+/// 通知（`id` のないリクエスト）の場合は `None` を返す
+async fn handle_request(
+    value: Value,
+    method_table: &MethodTable,
+    running: &Running,
+) -> Option<RpcMessage> {
+    // 構造が不正でも読み取れる `id` はエラーに含めて返す
+    let salvaged_id = salvage_id(&value);
+    let request = match serde_json::from_value::<RpcRequest>(value) {
+        Ok(request) => request,
+        Err(e) => {
+            log::warn!("エラー: {}", e);
+            let error = RpcError::invalid_request().with_data(e.to_string());
+            return Some(RpcMessage::Error(RpcErrorResponse::new(error, salvaged_id)));
+        }
+    };
+
+    if request.method == CANCEL_REQUEST {
+        return cancel_request(request, |id| running.cancel(id));
+    }
+
+    let Some(method) = method_table.get(&request.method) else {
+        let error = RpcError::method_not_found();
+        return request
+            .id
+            .map(|id| RpcMessage::Error(RpcErrorResponse::new(error, id)));
+    };
+
+    let result = match method.validate(&request.params, request.param_types.as_deref()) {
+        Ok(()) => run_method(method, request.params, request.id.as_ref(), running).await,
+        Err(detail) => Err(RpcError::invalid_params(format!(
+            "Invalid params: {}",
+            detail
+        ))),
+    };
+
+    // 通知は実行するだけで、結果もエラーも返さない
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => RpcMessage::Response(RpcResponse {
+            jsonrpc: Version,
+            result,
+            result_type: method.result_type().map(str::to_string),
+            id,
+        }),
+        Err(error) => RpcMessage::Error(RpcErrorResponse::new(error, id)),
+    })
+}
+
+/// `$/cancelRequest` を処理する。`id` 付きで送られた場合は null を返す
+///
+/// `cancel` は指定された `id` のリクエストを中断し、見つかったかどうかを返す
+fn cancel_request(request: RpcRequest, cancel: impl FnOnce(&RpcId) -> bool) -> Option<RpcMessage> {
+    let params = match request.params {
+        Params::ByName(params) => serde_json::from_value::<CancelParams>(Value::Object(params)),
+        Params::ByPosition(params) => serde_json::from_value::<CancelParams>(
+            serde_json::json!({ "id": params.into_iter().next() }),
+        ),
+    };
+    let result = match params {
+        Ok(params) => {
+            if cancel(&params.id) {
+                log::info!("リクエストを中断: {:?}", params.id);
+            }
+            Ok(Value::Null)
+        }
+        Err(e) => Err(RpcError::invalid_params(format!("Invalid params: {}", e))),
+    };
+
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => RpcMessage::Response(RpcResponse {
+            jsonrpc: Version,
+            result,
+            result_type: None,
+            id,
+        }),
+        Err(error) => RpcMessage::Error(RpcErrorResponse::new(error, id)),
+    })
+}
+
+/// ハンドラを別タスクで実行する
+///
+/// パニックしても接続ごと落ちないようにし、時間の上限を超えたらタスクを中断する。
+/// `id` のあるリクエストは `$/cancelRequest` で中断できるよう `running` に登録する。
+/// 始まる前に中断されていた場合はすぐに中断する
+async fn run_method(
+    method: &RpcMethod,
+    params: Params,
+    id: Option<&RpcId>,
+    running: &Running,
+) -> Result<Value, RpcError> {
+    let mut task = tokio::spawn(method.call(params));
+    let abort = task.abort_handle();
+    if let Some(id) = id {
+        running.insert(id.clone(), abort.clone());
+    }
+
+    // 上限を超えた場合は `Err(上限)`
+    let joined = match method.time_limit() {
+        Some(limit) => tokio::time::timeout(limit, &mut task)
+            .await
+            .map_err(|_| limit),
+        None => Ok((&mut task).await),
+    };
+    if let Some(id) = id {
+        running.remove(id, &abort);
+    }
+
+    match joined {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) if e.is_cancelled() => Err(RpcError::request_cancelled()),
+        Ok(Err(e)) => {
+            log::warn!("メソッドの実行に失敗: {}", e);
+            Err(RpcError::internal_error("Internal error"))
+        }
+        Err(limit) => {
+            task.abort();
+            log::info!("タイムアウトのためメソッドを中断: {:?}", limit);
+            Err(RpcError::request_timeout(limit))
+        }
+    }
+}
+
+/// 不正なリクエストから、有効な `id` だけを取り出す。読み取れない場合は null
+fn salvage_id(value: &Value) -> RpcId {
+    value
+        .get("id")
+        .and_then(|id| RpcId::deserialize(id).ok())
+        .unwrap_or(RpcId::Null)
+}
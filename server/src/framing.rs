@@ -0,0 +1,225 @@
+use std::{fmt, io, str::FromStr};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::transport::BoxReader;
+
+/// LSP 形式のヘッダーで本文の長さを表すフィールド
+const CONTENT_LENGTH: &str = "Content-Length";
+
+/// ストリーム上でメッセージを区切る方法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// 接続ごとに最初のバイトから判定する
+    #[default]
+    Auto,
+    /// 改行区切りの JSON
+    Line,
+    /// 4バイトのビッグエンディアンの長さ + 本文
+    LengthPrefixed,
+    /// `Content-Length: <n>\r\n\r\n` + 本文（LSP 形式）
+    ContentLength,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Framing::Auto),
+            "line" => Ok(Framing::Line),
+            "length" => Ok(Framing::LengthPrefixed),
+            "lsp" => Ok(Framing::ContentLength),
+            _ => Err(format!(
+                "unsupported framing '{}' (expected auto, line, length or lsp)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Framing::Auto => "auto",
+            Framing::Line => "line",
+            Framing::LengthPrefixed => "length",
+            Framing::ContentLength => "lsp",
+        };
+        f.write_str(name)
+    }
+}
+
+/// [`FrameReader::read_frame`] の結果
+pub(crate) enum Frame {
+    Message(Vec<u8>),
+    /// 上限を超えるメッセージ。残りは読まずに捨てる
+    TooLarge,
+    /// 接続が閉じられた
+    Closed,
+}
+
+/// 接続からメッセージを1つずつ読み出す
+pub(crate) struct FrameReader {
+    reader: BufReader<BoxReader>,
+    framing: Framing,
+    max_size: usize,
+}
+
+impl FrameReader {
+    pub(crate) fn new(reader: BoxReader, framing: Framing, max_size: usize) -> Self {
+        FrameReader {
+            reader: BufReader::new(reader),
+            framing,
+            max_size,
+        }
+    }
+
+    /// この接続で使っている区切り方。`Auto` の場合は最初のメッセージを読むまで確定しない
+    pub(crate) fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// メッセージを1つ読む
+    ///
+    /// 本文が `max_size` バイトを超える場合は、バッファに溜めずに [`Frame::TooLarge`] を返す。
+    /// 途中で止めると読みかけのメッセージは失われるので、`select!` の分岐では待たない
+    pub(crate) async fn read_frame(&mut self) -> io::Result<Frame> {
+        if self.framing == Framing::Auto {
+            let buf = self.reader.fill_buf().await?;
+            let Some(&first) = buf.first() else {
+                return Ok(Frame::Closed);
+            };
+            self.framing = detect(first);
+        }
+
+        match self.framing {
+            Framing::Auto | Framing::Line => self.read_line().await,
+            Framing::LengthPrefixed => self.read_length_prefixed().await,
+            Framing::ContentLength => self.read_content_length().await,
+        }
+    }
+
+    /// 改行までを読む。`max_size` を超えた時点で読むのをやめる
+    async fn read_line(&mut self) -> io::Result<Frame> {
+        let mut line = Vec::new();
+        loop {
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                // 改行のないまま閉じられた場合は、そこまでを1行とする
+                if line.is_empty() {
+                    return Ok(Frame::Closed);
+                }
+                break;
+            }
+            let (chunk, found) = match buf.iter().position(|&b| b == b'\n') {
+                Some(pos) => (&buf[..=pos], true),
+                None => (buf, false),
+            };
+            if line.len() + chunk.len() > self.max_size + usize::from(found) {
+                return Ok(Frame::TooLarge);
+            }
+            line.extend_from_slice(chunk);
+            let consumed = chunk.len();
+            self.reader.consume(consumed);
+            if found {
+                break;
+            }
+        }
+        Ok(Frame::Message(line.trim_ascii().to_vec()))
+    }
+
+    async fn read_length_prefixed(&mut self) -> io::Result<Frame> {
+        let mut prefix = [0; 4];
+        match self.reader.read_exact(&mut prefix).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Frame::Closed),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_be_bytes(prefix) as usize;
+        self.read_body(len).await
+    }
+
+    async fn read_content_length(&mut self) -> io::Result<Frame> {
+        let mut len = None;
+        loop {
+            // ヘッダーの行にも同じ上限をかける
+            let header = match self.read_line().await? {
+                Frame::Message(header) => header,
+                frame => return Ok(frame),
+            };
+            let header = String::from_utf8_lossy(&header);
+            // 空行でヘッダーが終わる
+            if header.is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                return Err(invalid_data(format!("malformed header: {}", header)));
+            };
+            // Content-Type など他のヘッダーは無視する
+            if name.trim().eq_ignore_ascii_case(CONTENT_LENGTH) {
+                let value = value.trim().parse::<usize>().map_err(|_| {
+                    invalid_data(format!("invalid {}: {}", CONTENT_LENGTH, value.trim()))
+                })?;
+                len = Some(value);
+            }
+        }
+        let len = len.ok_or_else(|| invalid_data(format!("missing {} header", CONTENT_LENGTH)))?;
+        self.read_body(len).await
+    }
+
+    async fn read_body(&mut self, len: usize) -> io::Result<Frame> {
+        if len > self.max_size {
+            return Ok(Frame::TooLarge);
+        }
+        let mut body = vec![0; len];
+        self.reader.read_exact(&mut body).await?;
+        Ok(Frame::Message(body))
+    }
+}
+
+/// 最初のバイトから区切り方を判定する
+///
+/// JSON は `{` `[` か空白で始まる。長さのプレフィックスは 16MiB 未満なら先頭が 0 になるので、
+/// 空白以外の制御文字で始まる場合は長さ付きとみなす
+fn detect(first: u8) -> Framing {
+    if first.eq_ignore_ascii_case(&CONTENT_LENGTH.as_bytes()[0]) {
+        Framing::ContentLength
+    } else if first.is_ascii_control() && !first.is_ascii_whitespace() {
+        Framing::LengthPrefixed
+    } else {
+        Framing::Line
+    }
+}
+
+/// `framing` に従ってメッセージを1つ書き込む
+pub(crate) async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    framing: Framing,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 32);
+    match framing {
+        Framing::Auto | Framing::Line => {
+            frame.extend_from_slice(payload);
+            frame.push(b'\n');
+        }
+        Framing::LengthPrefixed => {
+            let len = u32::try_from(payload.len())
+                .map_err(|_| invalid_data("message too large".to_string()))?;
+            frame.extend_from_slice(&len.to_be_bytes());
+            frame.extend_from_slice(payload);
+        }
+        Framing::ContentLength => {
+            frame.extend_from_slice(
+                format!("{}: {}\r\n\r\n", CONTENT_LENGTH, payload.len()).as_bytes(),
+            );
+            frame.extend_from_slice(payload);
+        }
+    }
+    writer.write_all(&frame).await
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,152 @@
+use std::{convert::Infallible, sync::Arc};
+
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header::{ALLOW, CONNECTION, CONTENT_TYPE, HeaderValue},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use tokio::{net::TcpStream, sync::watch};
+
+use crate::{
+    dispatch::{Running, handle_message, too_large_reply},
+    method::MethodTable,
+    rpc::{INVALID_REQUEST, METHOD_NOT_FOUND, RpcMessage, RpcReply},
+};
+
+/// JSON-RPC を受け付けるパス
+const RPC_PATH: &str = "/rpc";
+
+/// 1つの HTTP/1.1 接続を処理する
+///
+/// シャットダウンが通知されたら、処理中のリクエストに応答してから接続を閉じる
+pub(crate) async fn handle_http_connection(
+    stream: TcpStream,
+    methods: Arc<MethodTable>,
+    max_size: usize,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let service = service_fn(move |request| {
+        let methods = Arc::clone(&methods);
+        async move { Ok::<_, Infallible>(handle_http_request(request, &methods, max_size).await) }
+    });
+
+    let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(conn);
+
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown.changed() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(e) = result {
+        log::warn!("HTTP エラー: {}", e);
+    }
+    log::info!("接続終了");
+}
+
+/// `POST /rpc` のボディを JSON-RPC メッセージとして処理する
+async fn handle_http_request(
+    request: Request<Incoming>,
+    methods: &Arc<MethodTable>,
+    max_size: usize,
+) -> Response<Full<Bytes>> {
+    if request.uri().path() != RPC_PATH {
+        return empty_response(StatusCode::NOT_FOUND);
+    }
+    if request.method() != Method::POST {
+        let mut response = empty_response(StatusCode::METHOD_NOT_ALLOWED);
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("POST"));
+        return response;
+    }
+    let is_json = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|value| value.starts_with("application/json"));
+    if !is_json {
+        return empty_response(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    let body = match Limited::new(request.into_body(), max_size).collect().await {
+        Ok(body) => body.to_bytes(),
+        // 残りのボディは読まずに接続を閉じる
+        Err(e) if e.is::<LengthLimitError>() => {
+            log::warn!(
+                "エラー: メッセージが上限 ({} バイト) を超えています",
+                max_size
+            );
+            let mut response = json_response(&too_large_reply(max_size));
+            *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+            response
+                .headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+            return response;
+        }
+        Err(e) => {
+            log::warn!("エラー: {}", e);
+            return empty_response(StatusCode::BAD_REQUEST);
+        }
+    };
+    // UTF-8 として不正なバイトは置換文字として扱う
+    let message = String::from_utf8_lossy(&body);
+    log::debug!("受信: {}", message);
+
+    // 通知だけの場合は 204 No Content
+    // 1つの HTTP リクエストの中で完結するので、`$/cancelRequest` は同じバッチ内でだけ効く
+    let Some(reply) = handle_message(&message, methods, &Running::default()).await else {
+        return empty_response(StatusCode::NO_CONTENT);
+    };
+
+    let mut response = json_response(&reply);
+    if response.status() == StatusCode::OK {
+        *response.status_mut() = status_code(&reply);
+    }
+    response
+}
+
+/// 返信を JSON のボディにする
+fn json_response(reply: &RpcReply) -> Response<Full<Bytes>> {
+    match serde_json::to_string(reply) {
+        Ok(json_response) => {
+            log::debug!("Response sent successfully: {}", json_response);
+            let mut response = Response::new(Full::new(Bytes::from(json_response)));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        }
+        Err(e) => {
+            log::warn!("Error converting response to JSON: {}", e);
+            empty_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// JSON-RPC over HTTP の慣例に従ってステータスコードを決める
+///
+/// バッチと成功レスポンスは 200、単体のエラーはエラーコードに応じて決める
+fn status_code(reply: &RpcReply) -> StatusCode {
+    let RpcReply::Single(RpcMessage::Error(response)) = reply else {
+        return StatusCode::OK;
+    };
+    match response.error.code {
+        INVALID_REQUEST => StatusCode::BAD_REQUEST,
+        METHOD_NOT_FOUND => StatusCode::NOT_FOUND,
+        // Parse error・Invalid params・Internal error・サーバー定義のエラー
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn empty_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    response
+}
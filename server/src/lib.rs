@@ -0,0 +1,44 @@
+//! Unix Domain Socket / TCP / HTTP / WebSocket 上で JSON-RPC 2.0 を提供するサーバーライブラリ
+//!
+//! ```ignore
+//! RpcServer::builder()
+//!     .socket_path("/tmp/rpc.sock")
+//!     .method("floor", RpcMethod::typed(["x"], floor))
+//!     .serve()
+//!     .await?;
+//! ```
+//!
+//! メソッドをまとめて宣言する場合は [`rpc_service`] を使う
+
+pub mod client;
+mod dispatch;
+pub mod framing;
+#[cfg(feature = "http")]
+mod http;
+pub mod method;
+pub mod methods;
+pub mod rpc;
+pub mod server;
+pub mod service;
+pub mod transport;
+pub mod typed;
+#[cfg(feature = "websocket")]
+pub mod ws;
+
+pub use client::{ClientError, RpcClient};
+pub use framing::Framing;
+pub use method::{MethodTable, ParamType, RpcMethod};
+pub use rpc::{Params, RpcError, RpcId};
+pub use server::{RpcServer, RpcServerBuilder};
+pub use server_macros::rpc_service;
+pub use service::RpcService;
+pub use transport::ListenAddr;
+pub use typed::RpcType;
+#[cfg(feature = "websocket")]
+pub use ws::Notifier;
+
+/// マクロが生成するコードから使う
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
+}
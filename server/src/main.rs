@@ -1,263 +1,220 @@
-use std::{collections::HashMap, path::Path};
-
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::UnixListener,
+use std::{env, path::PathBuf, time::Duration};
+
+use anyhow::{Context, bail};
+use server::{
+    Framing, ListenAddr, MethodTable, RpcServer,
+    methods::create_method_table,
+    server::{
+        DEFAULT_MAX_CONCURRENT_REQUESTS, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_REQUEST_TIMEOUT,
+        DEFAULT_SHUTDOWN_TIMEOUT, DEFAULT_SOCKET_PATH,
+    },
+    transport::SUPPORTED_SCHEMES,
 };
 
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-
-type RpcMethod = fn(&Value) -> Result<(String, String), String>;
-
-const SERVER_PATH: &str = "/tmp/rpc.sock";
-
-/// RPC リクエスト
-
-#[derive(Debug, Serialize, Deserialize)]
-struct RpcRequest {
-    method: String,
-    params: serde_json::Value, // 柔軟に受け取るため
-    param_types: Option<Vec<String>>,
-    id: u64,
+/// ソケットパスを指定する環境変数
+const SOCKET_ENV: &str = "RPC_SOCKET";
+
+/// コマンドライン引数
+#[derive(Debug, Default)]
+struct Args {
+    /// `--socket` で指定された Unix Domain Socket のパス
+    sockets: Vec<PathBuf>,
+    /// `--listen` で指定された待ち受けアドレス
+    listen: Vec<ListenAddr>,
+    request_timeout: Option<Duration>,
+    shutdown_timeout: Option<Duration>,
+    framing: Framing,
+    max_message_size: Option<usize>,
+    max_concurrent_requests: Option<usize>,
+    /// 受信・送信したメッセージの本文も表示する
+    verbose: bool,
+    help: bool,
 }
 
-/// RPC レスポンス
-#[derive(Debug, Serialize, Deserialize)]
-struct RpcResponse {
-    result: String,
-    result_type: String,
-    id: u64,
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Args> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        // `--name value` と `--name=value` のどちらでも受け付ける
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |what: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .with_context(|| format!("{} requires {}", name, what))
+        };
+        match name {
+            "-h" | "--help" => parsed.help = true,
+            "-v" | "--verbose" => parsed.verbose = true,
+            "--socket" => parsed.sockets.push(PathBuf::from(value("a path")?)),
+            "--listen" => parsed
+                .listen
+                .push(parse_listen_addr(&value("an address")?)?),
+            "--framing" => parsed.framing = parse_framing(&value("a mode")?)?,
+            "--max-concurrent-requests" => {
+                let count = value("a number")?
+                    .parse::<usize>()
+                    .context("--max-concurrent-requests must be a number")?;
+                parsed.max_concurrent_requests = Some(count);
+            }
+            "--request-timeout" => {
+                let secs = value("seconds")?
+                    .parse::<u64>()
+                    .context("--request-timeout must be a number of seconds")?;
+                parsed.request_timeout = Some(Duration::from_secs(secs));
+            }
+            "--shutdown-timeout" => {
+                let secs = value("seconds")?
+                    .parse::<u64>()
+                    .context("--shutdown-timeout must be a number of seconds")?;
+                parsed.shutdown_timeout = Some(Duration::from_secs(secs));
+            }
+            "--max-message-size" => {
+                let bytes = value("bytes")?
+                    .parse::<usize>()
+                    .context("--max-message-size must be a number of bytes")?;
+                parsed.max_message_size = Some(bytes);
+            }
+            _ => bail!("unknown argument: {} (see --help)", arg),
+        }
+    }
+    Ok(parsed)
 }
 
-/// RPC エラー
-#[derive(Debug, Serialize, Deserialize)]
-struct RpcError {
-    code: i32,
-    message: String,
+fn parse_listen_addr(addr: &str) -> anyhow::Result<ListenAddr> {
+    addr.parse::<ListenAddr>().map_err(anyhow::Error::msg)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RpcErrorResponse {
-    error: RpcError,
-    id: u64,
+fn parse_framing(framing: &str) -> anyhow::Result<Framing> {
+    framing.parse::<Framing>().map_err(anyhow::Error::msg)
 }
 
-#[tokio::main]
-async fn main() {
-    if Path::new(SERVER_PATH).exists() {
-        std::fs::remove_file(SERVER_PATH).unwrap();
-    }
-
-    let method_table = create_method_table();
-
-    let listener = UnixListener::bind(SERVER_PATH).unwrap();
-    loop {
-        match listener.accept().await {
-            Ok((stream, _addr)) => {
-                println!("New client connected!");
-
-                // streamを分割
-                let (read_half, mut write_half) = stream.into_split();
-                let mut reader = BufReader::new(read_half);
-                let mut lines = String::new();
-
-                match reader.read_line(&mut lines).await {
-                    Ok(0) => println!("接続終了"),
-                    Ok(_) => {
-                        let trimmed_lines = lines.trim();
-                        println!("受信: {}", trimmed_lines);
-
-                        // JSONのパース処理
-                        match serde_json::from_str::<RpcRequest>(trimmed_lines) {
-                            Ok(request) => {
-                                let response = if let Some(method_fn) =
-                                    method_table.get(&request.method)
-                                {
-                                    match method_fn(&request.params) {
-                                        Ok((result, result_type)) => RpcResponse {
-                                            result,
-                                            result_type,
-                                            id: request.id,
-                                        },
-                                        Err(err_msg) => {
-                                            let error_response = RpcErrorResponse {
-                                                error: RpcError {
-                                                    code: -32602,
-                                                    message: err_msg,
-                                                },
-                                                id: request.id,
-                                            };
-                                            // エラーレスポンスを送信して続行
-                                            if let Ok(error_json) =
-                                                serde_json::to_string(&error_response)
-                                            {
-                                                let message = format!("{}\n", error_json);
-                                                let _ =
-                                                    write_half.write_all(message.as_bytes()).await;
-                                            }
-                                            continue;
-                                        }
-                                    }
-                                } else {
-                                    let error_response = RpcErrorResponse {
-                                        error: RpcError {
-                                            code: -32601,
-                                            message: "Method not found".to_string(),
-                                        },
-                                        id: request.id,
-                                    };
+/// ライブラリのログを標準出力に書き出す
+struct StdoutLogger;
 
-                                    if let Ok(error_json) = serde_json::to_string(&error_response) {
-                                        let message = format!("{}\n", error_json);
-                                        let _ = write_half.write_all(message.as_bytes()).await;
-                                    }
-                                    continue;
-                                };
-
-                                // JSONに変換する
-                                match serde_json::to_string(&response) {
-                                    Ok(json_response) => {
-                                        let message = format!("{}\n", json_response);
-                                        if let Err(e) =
-                                            write_half.write_all(message.as_bytes()).await
-                                        {
-                                            println!("Error sending response: {}", e);
-                                        } else {
-                                            println!(
-                                                "Response sent successfully: {}",
-                                                json_response
-                                            );
-                                        }
-                                    }
-                                    Err(e) => {
-                                        println!("Error converting response to JSON: {}", e);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                println!("エラー: {}", e);
-
-                                let error_response = RpcErrorResponse {
-                                    error: RpcError {
-                                        code: -32602,
-                                        message: "Invalid params".to_string(),
-                                    },
-                                    id: 0,
-                                };
+impl log::Log for StdoutLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // 依存クレートのログは出さない
+        metadata.level() <= log::max_level() && metadata.target().starts_with("server")
+    }
 
-                                match serde_json::to_string(&error_response) {
-                                    Ok(error_response_json) => {
-                                        let message = format!("{}\n", error_response_json);
-                                        if let Err(e) =
-                                            write_half.write_all(message.as_bytes()).await
-                                        {
-                                            println!("Error sending error response: {}", e);
-                                        } else {
-                                            println!(
-                                                "Error response sent successfully: {}",
-                                                error_response_json
-                                            );
-                                        }
-                                    }
-                                    Err(e) => {
-                                        println!("Error converting error response to JSON: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!("エラー: {}", e);
-                    }
-                }
-            }
-            Err(e) => {
-                println!("Connection failed: {}", e);
-            }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            println!("{}", record.args());
         }
     }
-}
 
-fn create_method_table() -> HashMap<String, RpcMethod> {
-    let mut methods = HashMap::new();
-    methods.insert("floor".to_string(), rpc_floor as RpcMethod);
-    methods.insert("nroot".to_string(), rpc_nroot as RpcMethod);
-    methods.insert("reverse".to_string(), rpc_reverse as RpcMethod);
-    methods.insert("valid_anagram".to_string(), rpc_valid_anagram as RpcMethod);
-    methods.insert("sort".to_string(), rpc_sort as RpcMethod);
-    methods
+    fn flush(&self) {}
 }
 
-fn rpc_floor(params: &Value) -> Result<(String, String), String> {
-    if let Some(arr) = params.as_array() {
-        if let Some(num) = arr.first().and_then(|v| v.as_f64()) {
-            let result = num.floor();
-            return Ok((result.to_string(), "int".to_string()));
+static LOGGER: StdoutLogger = StdoutLogger;
+
+fn print_help(methods: &MethodTable) {
+    println!(
+        "Usage: server [--socket <path>] [--listen <addr>]... [--framing <mode>] [--max-message-size <bytes>] [--max-concurrent-requests <n>] [--request-timeout <secs>] [--shutdown-timeout <secs>] [--verbose]"
+    );
+    println!();
+    println!("Options:");
+    println!(
+        "  --socket <path>                Unix Domain Socket のパス (環境変数 {}, デフォルト: {})",
+        SOCKET_ENV, DEFAULT_SOCKET_PATH
+    );
+    println!(
+        "  --listen <addr>                Unix Domain Socket に加えて待ち受けるアドレス ({}、複数指定可)",
+        SUPPORTED_SCHEMES.join(", ")
+    );
+    println!(
+        "  --framing <mode>               Unix / TCP でのメッセージの区切り方 (auto, line, length, lsp、デフォルト: {})",
+        Framing::default()
+    );
+    println!(
+        "  --max-message-size <bytes>     1メッセージの大きさの上限 (デフォルト: {})",
+        DEFAULT_MAX_MESSAGE_SIZE
+    );
+    println!(
+        "  --max-concurrent-requests <n>  1つの接続で同時に処理するリクエストの数 (デフォルト: {})",
+        DEFAULT_MAX_CONCURRENT_REQUESTS
+    );
+    println!(
+        "  --request-timeout <secs>       メソッドの実行時間の上限の秒数 (デフォルト: {})",
+        DEFAULT_REQUEST_TIMEOUT.as_secs()
+    );
+    println!(
+        "  --shutdown-timeout <secs>      停止時に処理中のリクエストを待つ秒数 (デフォルト: {})",
+        DEFAULT_SHUTDOWN_TIMEOUT.as_secs()
+    );
+    println!("  -v, --verbose                  受信・送信したメッセージの本文も表示する");
+    println!("  -h, --help                     このヘルプを表示する");
+    println!();
+    println!("値は --socket=<path> のように = で続けても指定できる");
+    println!();
+    println!("Methods:");
+
+    let mut names = methods.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let method = &methods[name];
+        let params = match method.signature() {
+            Some(signature) => signature
+                .iter()
+                .map(|(param, ty)| format!("{}: {}", param, ty))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => "..".to_string(),
+        };
+        match method.result_type() {
+            Some(result_type) => println!("  {}({}) -> {}", name, params, result_type),
+            None => println!("  {}({})", name, params),
         }
     }
-    Err("Invalid params".to_string())
 }
 
-fn rpc_nroot(params: &Value) -> Result<(String, String), String> {
-    if let Some(arr) = params.as_array() {
-        if arr.len() >= 2 {
-            if let (Some(n), Some(x)) = (
-                arr.first().and_then(|v| v.as_f64()),
-                arr.get(1).and_then(|v| v.as_f64()),
-            ) {
-                let result = x.powf(1.0 / n);
-                return Ok((result.to_string(), "double".to_string()));
-            }
-        }
-    }
-    Err("Invalid params".to_string())
-}
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = parse_args(env::args().skip(1))?;
+    let methods = create_method_table();
 
-fn rpc_reverse(params: &Value) -> Result<(String, String), String> {
-    if let Some(arr) = params.as_array() {
-        if let Some(str) = arr.first().and_then(|v| v.as_str()) {
-            let result = str.chars().rev().collect::<String>();
-            return Ok((result, "string".to_string()));
-        }
+    if args.help {
+        print_help(&methods);
+        return Ok(());
     }
-    Err("Invalid params".to_string())
-}
 
-fn rpc_valid_anagram(params: &Value) -> Result<(String, String), String> {
-    if let Some(arr) = params.as_array() {
-        if arr.len() >= 2 {
-            if let (Some(str1), Some(str2)) = (
-                arr.first().and_then(|v| v.as_str()),
-                arr.get(1).and_then(|v| v.as_str()),
-            ) {
-                let mut char1 = str1.chars().collect::<Vec<char>>();
-                let mut char2 = str2.chars().collect::<Vec<char>>();
-                char1.sort();
-                char2.sort();
-                let is_anagram = char1 == char2;
-                return Ok((is_anagram.to_string(), "bool".to_string()));
-            }
+    log::set_logger(&LOGGER).map_err(anyhow::Error::msg)?;
+    log::set_max_level(if args.verbose {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    });
+
+    // Unix Domain Socket の優先順位: --socket > RPC_SOCKET > デフォルト
+    // --listen のアドレスはそれに加えて待ち受ける。どちらも指定がなければデフォルトのソケットだけ
+    let mut sockets = args.sockets;
+    if sockets.is_empty() {
+        if let Some(socket_path) = env::var_os(SOCKET_ENV) {
+            sockets.push(PathBuf::from(socket_path));
+        } else if args.listen.is_empty() {
+            sockets.push(PathBuf::from(DEFAULT_SOCKET_PATH));
         }
     }
-    Err("Invalid params".to_string())
-}
+    let listen = sockets.into_iter().map(ListenAddr::Unix).chain(args.listen);
 
-fn rpc_sort(params: &Value) -> Result<(String, String), String> {
-    if let Some(arr) = params.as_array() {
-        if let Some(str_arr) = arr.first().and_then(|v| v.as_array()) {
-            let mut strings: Vec<String> = Vec::new();
-            for item in str_arr {
-                if let Some(s) = item.as_str() {
-                    strings.push(s.to_string());
-                } else {
-                    return Err("Invalid params".to_string());
-                }
-            }
-            strings.sort();
-            let result = serde_json::to_string(&strings).unwrap();
-            return Ok((result, "string".to_string()));
-        }
+    let mut builder = RpcServer::builder();
+    for addr in listen {
+        builder = builder.listen(addr);
     }
-    Err("Invalid params".to_string())
+    builder
+        .max_concurrent_requests(
+            args.max_concurrent_requests
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS),
+        )
+        .request_timeout(args.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT))
+        .shutdown_timeout(args.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT))
+        .framing(args.framing)
+        .max_message_size(args.max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE))
+        .methods(methods)
+        .serve()
+        .await?;
+    Ok(())
 }
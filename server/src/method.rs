@@ -0,0 +1,246 @@
+use std::{collections::HashMap, fmt, future::Future, pin::Pin, str::FromStr, time::Duration};
+
+use serde_json::Value;
+
+use crate::rpc::{Params, RpcError};
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+pub type RpcHandler = Box<dyn Fn(Params) -> BoxFuture<Result<Value, RpcError>> + Send + Sync>;
+pub type MethodTable = HashMap<String, RpcMethod>;
+
+/// RPC パラメータの型（`param_types` で宣言される型名に対応する）
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamType {
+    Int,
+    Double,
+    String,
+    Bool,
+    Array(Box<ParamType>),
+}
+
+impl ParamType {
+    /// 要素の型が `item` の配列型
+    pub fn array_of(item: ParamType) -> Self {
+        ParamType::Array(Box::new(item))
+    }
+
+    /// JSON の値がこの型に合うかどうか
+    pub fn matches(&self, value: &Value) -> bool {
+        match self {
+            ParamType::Int => value.is_i64() || value.is_u64(),
+            ParamType::Double => value.is_number(),
+            ParamType::String => value.is_string(),
+            ParamType::Bool => value.is_boolean(),
+            ParamType::Array(item) => value
+                .as_array()
+                .is_some_and(|values| values.iter().all(|v| item.matches(v))),
+        }
+    }
+
+    /// クライアントが宣言した型 `declared` をこの型の引数として受け付けるかどうか
+    ///
+    /// int は double として扱えるため受け付ける
+    pub fn accepts(&self, declared: &ParamType) -> bool {
+        match (self, declared) {
+            (ParamType::Double, ParamType::Int) => true,
+            (ParamType::Array(expected), ParamType::Array(declared)) => expected.accepts(declared),
+            (expected, declared) => expected == declared,
+        }
+    }
+}
+
+impl FromStr for ParamType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(item) = s.strip_suffix("[]") {
+            return item.parse().map(ParamType::array_of);
+        }
+        match s {
+            "int" => Ok(ParamType::Int),
+            "double" => Ok(ParamType::Double),
+            "string" => Ok(ParamType::String),
+            "bool" => Ok(ParamType::Bool),
+            _ => Err(format!("unknown type '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamType::Int => write!(f, "int"),
+            ParamType::Double => write!(f, "double"),
+            ParamType::String => write!(f, "string"),
+            ParamType::Bool => write!(f, "bool"),
+            ParamType::Array(item) => write!(f, "{}[]", item),
+        }
+    }
+}
+
+/// エラーメッセージ用に JSON の値の型名を返す
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.is_f64() => "double",
+        Value::Number(_) => "int",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// メソッドテーブルに登録する RPC メソッド
+///
+/// ```ignore
+/// RpcMethod::new(rpc_nroot)
+///     .params(vec![("n", ParamType::Int), ("x", ParamType::Int)])
+///     .returns("double")
+/// ```
+pub struct RpcMethod {
+    handler: RpcHandler,
+    /// 引数のシグネチャ（名前と型）。`None` の場合は検査しない
+    params: Option<Vec<(&'static str, ParamType)>>,
+    /// レスポンスの `result_type` に載せる戻り値の型
+    result_type: Option<String>,
+    /// 実行時間の上限。`None` の場合はサーバーのデフォルトに従う
+    timeout: Option<Duration>,
+}
+
+impl RpcMethod {
+    /// 非同期関数や状態をキャプチャしたクロージャをハンドラとして登録する
+    pub fn new<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value, RpcError>> + Send + 'static,
+    {
+        RpcMethod {
+            handler: Box::new(move |params| Box::pin(handler(params))),
+            params: None,
+            result_type: None,
+            timeout: None,
+        }
+    }
+
+    /// 引数のシグネチャを宣言する
+    pub fn params(mut self, params: Vec<(&'static str, ParamType)>) -> Self {
+        self.params = Some(params);
+        self
+    }
+
+    /// レスポンスの `result_type` に載せる戻り値の型を宣言する
+    pub fn returns(mut self, result_type: impl Into<String>) -> Self {
+        self.result_type = Some(result_type.into());
+        self
+    }
+
+    /// 実行時間の上限を設定する（サーバーのデフォルトより優先される）
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// 宣言された引数のシグネチャ
+    pub fn signature(&self) -> Option<&[(&'static str, ParamType)]> {
+        self.params.as_deref()
+    }
+
+    pub fn result_type(&self) -> Option<&str> {
+        self.result_type.as_deref()
+    }
+
+    /// 実行時間の上限
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// 上限が設定されていなければ `timeout` を使う
+    pub(crate) fn default_timeout(&mut self, timeout: Duration) {
+        self.timeout.get_or_insert(timeout);
+    }
+
+    /// ハンドラを呼び出す
+    pub fn call(&self, params: Params) -> BoxFuture<Result<Value, RpcError>> {
+        (self.handler)(params)
+    }
+
+    /// 宣言された `param_types` と実際の値をシグネチャと照合する
+    ///
+    /// 不一致の場合は問題のある引数を示すメッセージを返す
+    pub fn validate(&self, params: &Params, param_types: Option<&[String]>) -> Result<(), String> {
+        let Some(signature) = &self.params else {
+            return Ok(());
+        };
+
+        if let Some(param_types) = param_types {
+            if param_types.len() != signature.len() {
+                return Err(format!(
+                    "expected {} param_types, got {}",
+                    signature.len(),
+                    param_types.len()
+                ));
+            }
+            for (index, ((name, expected), declared)) in
+                signature.iter().zip(param_types).enumerate()
+            {
+                let declared = declared
+                    .parse::<ParamType>()
+                    .map_err(|e| format!("param_types[{}]: {}", index, e))?;
+                if !expected.accepts(&declared) {
+                    return Err(format!(
+                        "params[{}] ({}): declared as {}, expected {}",
+                        index, name, declared, expected
+                    ));
+                }
+            }
+        }
+
+        match params {
+            Params::ByPosition(values) => {
+                if values.len() != signature.len() {
+                    return Err(format!(
+                        "expected {} params, got {}",
+                        signature.len(),
+                        values.len()
+                    ));
+                }
+            }
+            Params::ByName(values) => {
+                if let Some(unknown) = values
+                    .keys()
+                    .find(|key| !signature.iter().any(|(name, _)| name == key))
+                {
+                    return Err(format!("unknown param '{}'", unknown));
+                }
+            }
+        }
+
+        for (index, (name, expected)) in signature.iter().enumerate() {
+            let Some(value) = params.get(index, name) else {
+                return Err(format!("params[{}] ({}): missing", index, name));
+            };
+            if !expected.matches(value) {
+                return Err(format!(
+                    "params[{}] ({}): expected {}, got {}",
+                    index,
+                    name,
+                    expected,
+                    json_type_name(value)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<F, Fut> From<F> for RpcMethod
+where
+    F: Fn(Params) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, RpcError>> + Send + 'static,
+{
+    fn from(handler: F) -> Self {
+        RpcMethod::new(handler)
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    method::MethodTable,
+    rpc::RpcError,
+    rpc_service,
+    service::{self, RpcService},
+};
+
+/// 計算できない引数（0乗根など）を表すエラーコード
+pub const DOMAIN_ERROR: i32 = -32000;
+
+/// 数値の計算（`math` の名前空間に置く）
+pub struct Math;
+
+/// 文字列の操作（`string` の名前空間に置く）
+pub struct Strings;
+
+/// 組み込みの RPC メソッドを名前空間ごとに登録したメソッドテーブルを作る
+pub fn create_method_table() -> MethodTable {
+    let mut methods = MethodTable::new();
+    service::mount(&mut methods, "math", Math.method_table());
+    service::mount(&mut methods, "string", Strings.method_table());
+    methods
+}
+
+#[rpc_service(crate = crate)]
+impl Math {
+    fn floor(x: f64) -> Result<i64, RpcError> {
+        let floored = x.floor();
+        // `as` は範囲外の値を飽和させるので、int に収まらない場合はエラーにする
+        // （`i64::MAX as f64` は 2^63 に丸められるため上限は含まない）
+        if !(i64::MIN as f64..i64::MAX as f64).contains(&floored) {
+            return Err(
+                RpcError::server_error(DOMAIN_ERROR, "result is out of the int range")
+                    .with_data(serde_json::json!({ "x": x })),
+            );
+        }
+        Ok(floored as i64)
+    }
+
+    fn nroot(n: i64, x: i64) -> Result<f64, RpcError> {
+        if n == 0 {
+            return Err(RpcError::server_error(DOMAIN_ERROR, "n must not be zero"));
+        }
+        // 負の数の偶数乗根は実数にならない
+        if x < 0 && n % 2 == 0 {
+            return Err(
+                RpcError::server_error(DOMAIN_ERROR, "even root of a negative number")
+                    .with_data(serde_json::json!({ "n": n, "x": x })),
+            );
+        }
+        let (n_f, x_f) = (n as f64, x as f64);
+        let result = if x_f < 0.0 {
+            -(-x_f).powf(1.0 / n_f)
+        } else {
+            x_f.powf(1.0 / n_f)
+        };
+        // 0 の負の乗根などは無限大になり、JSON では null になってしまう
+        if !result.is_finite() {
+            return Err(
+                RpcError::server_error(DOMAIN_ERROR, "result is not a finite number")
+                    .with_data(serde_json::json!({ "n": n, "x": x })),
+            );
+        }
+        Ok(result)
+    }
+}
+
+#[rpc_service(crate = crate)]
+impl Strings {
+    fn reverse(s: String) -> String {
+        s.chars().rev().collect()
+    }
+
+    fn valid_anagram(str1: String, str2: String) -> bool {
+        let mut char1 = str1.chars().collect::<Vec<char>>();
+        let mut char2 = str2.chars().collect::<Vec<char>>();
+        char1.sort();
+        char2.sort();
+        char1 == char2
+    }
+
+    async fn sort(#[rpc(name = "strArr")] mut str_arr: Vec<String>) -> Vec<String> {
+        str_arr.sort();
+        str_arr
+    }
+}
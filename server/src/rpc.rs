@@ -1,27 +1,230 @@
-// use serde::{Deserialize, Serialize};
-
-// /// RPC リクエスト
-
-// #[derive(Debug, Serialize, Deserialize)]
-// pub struct RpcRequest {
-//     pub method: String,
-//     pub params: Vec<serde_json::Value>,
-//     pub param_types: Vec<String>,
-//     pub id: u64,
-// }
-
-// /// RPC レスポンス
-// #[derive(Debug, Serialize, Deserialize)]
-// pub struct RpcResponse {
-//     pub result: Option<serde_json::Value>,
-//     pub result_type: Option<String>,
-//     pub error: Option<RpcError>,
-//     pub id: u64,
-// }
-
-// /// RPC エラー
-// #[derive(Debug, Serialize, Deserialize)]
-// pub struct RpcError {
-//     pub code: i32,
-//     pub message: String,
-// }
+use std::{fmt, ops::RangeInclusive, time::Duration};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+/// JSON-RPC 2.0 のエラーコード
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+
+/// アプリケーションが定義できるサーバーエラーのコード
+pub const SERVER_ERROR_RANGE: RangeInclusive<i32> = -32099..=-32000;
+
+/// `$/cancelRequest` でリクエストが中断された（LSP と同じコード）
+pub const REQUEST_CANCELLED: i32 = -32800;
+
+/// メソッドの実行が時間の上限を超えた
+///
+/// アプリケーションのエラーと区別できるよう [`SERVER_ERROR_RANGE`] の外に置き、LSP のコードとも重ねない
+pub const REQUEST_TIMEOUT: i32 = -32810;
+
+/// `"jsonrpc": "2.0"` フィールド
+///
+/// "2.0" 以外の値はデシリアライズ時に拒否する
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Version;
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("2.0")
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        if version == "2.0" {
+            Ok(Version)
+        } else {
+            Err(serde::de::Error::custom(format!(
+                "unsupported jsonrpc version: {}",
+                version
+            )))
+        }
+    }
+}
+
+/// リクエスト ID（文字列・数値・null）
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcId {
+    Number(serde_json::Number),
+    String(String),
+    Null,
+}
+
+/// RPC パラメータ（位置指定または名前指定）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Params {
+    ByPosition(Vec<Value>),
+    ByName(Map<String, Value>),
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::ByPosition(Vec::new())
+    }
+}
+
+impl Params {
+    /// 位置 `index` または名前 `name` で引数を取り出す
+    pub fn get(&self, index: usize, name: &str) -> Option<&Value> {
+        match self {
+            Params::ByPosition(values) => values.get(index),
+            Params::ByName(values) => values.get(name),
+        }
+    }
+}
+
+/// RPC リクエスト
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: Version,
+    pub method: String,
+    #[serde(default)]
+    pub params: Params,
+    pub param_types: Option<Vec<String>>,
+    /// 省略された場合は通知（レスポンスを返さない）
+    #[serde(default, deserialize_with = "deserialize_id")]
+    pub id: Option<RpcId>,
+}
+
+/// `"id": null` と `id` の省略を区別するため、存在する場合は必ず `Some` にする
+fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<RpcId>, D::Error> {
+    RpcId::deserialize(deserializer).map(Some)
+}
+
+/// RPC レスポンス
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: Version,
+    pub result: Value,
+    /// 戻り値の型（任意のメタデータ）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_type: Option<String>,
+    pub id: RpcId,
+}
+
+/// RPC エラー
+///
+/// ハンドラはこれを返し、ディスパッチャはコード・メッセージ・`data` をそのままクライアントへ返す
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn parse_error() -> Self {
+        RpcError::new(PARSE_ERROR, "Parse error")
+    }
+
+    pub fn invalid_request() -> Self {
+        RpcError::new(INVALID_REQUEST, "Invalid Request")
+    }
+
+    pub fn method_not_found() -> Self {
+        RpcError::new(METHOD_NOT_FOUND, "Method not found")
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        RpcError::new(INVALID_PARAMS, message)
+    }
+
+    pub fn internal_error(message: impl Into<String>) -> Self {
+        RpcError::new(INTERNAL_ERROR, message)
+    }
+
+    pub fn request_timeout(timeout: Duration) -> Self {
+        RpcError::new(REQUEST_TIMEOUT, "Request timed out")
+            .with_data(format!("exceeded {} ms", timeout.as_millis()))
+    }
+
+    pub fn request_cancelled() -> Self {
+        RpcError::new(REQUEST_CANCELLED, "Request cancelled")
+    }
+
+    /// アプリケーション定義のエラー
+    ///
+    /// # Panics
+    ///
+    /// `code` が [`SERVER_ERROR_RANGE`] の外の場合
+    pub fn server_error(code: i32, message: impl Into<String>) -> Self {
+        assert!(
+            SERVER_ERROR_RANGE.contains(&code),
+            "server error code {} is outside {:?}",
+            code,
+            SERVER_ERROR_RANGE
+        );
+        RpcError::new(code, message)
+    }
+
+    /// 追加情報（`data`）を付ける
+    pub fn with_data(mut self, data: impl Into<Value>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcErrorResponse {
+    pub jsonrpc: Version,
+    pub error: RpcError,
+    pub id: RpcId,
+}
+
+impl RpcErrorResponse {
+    pub fn new(error: RpcError, id: RpcId) -> Self {
+        RpcErrorResponse {
+            jsonrpc: Version,
+            error,
+            id,
+        }
+    }
+}
+
+/// サーバーからクライアントへ送る通知
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcNotification {
+    pub jsonrpc: Version,
+    pub method: String,
+    pub params: Value,
+}
+
+/// サーバーから送信するメッセージ（成功またはエラー）
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcMessage {
+    Response(RpcResponse),
+    Error(RpcErrorResponse),
+}
+
+/// 受信したメッセージへの返信（単体またはバッチ）
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RpcReply {
+    Single(RpcMessage),
+    Batch(Vec<RpcMessage>),
+}
@@ -0,0 +1,434 @@
+use std::{future::Future, io, path::PathBuf, sync::Arc, time::Duration};
+
+use tokio::{
+    io::AsyncWriteExt,
+    signal::unix::{SignalKind, signal},
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
+
+use crate::{
+    dispatch::{InFlight, too_large_reply},
+    framing::{Frame, FrameReader, Framing, write_frame},
+    method::{MethodTable, RpcMethod},
+    service::{self, RpcService},
+    transport::{BoxReader, BoxWriter, Connection, ListenAddr, Listener},
+};
+
+#[cfg(feature = "websocket")]
+use crate::ws::Notifier;
+
+/// デフォルトのソケットパス
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/rpc.sock";
+
+/// 1メッセージの大きさの上限のデフォルト
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// メソッドの実行時間の上限のデフォルト
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 1つの接続で同時に処理するリクエストの数のデフォルト
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 16;
+
+/// シャットダウン時に処理中のリクエストを待つ時間のデフォルト
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Unix Domain Socket や TCP（フィーチャーによっては HTTP / WebSocket も）で
+/// JSON-RPC リクエストを受け付けるサーバー
+pub struct RpcServer {
+    listen_addrs: Vec<ListenAddr>,
+    methods: Arc<MethodTable>,
+    shutdown_timeout: Duration,
+    framing: Framing,
+    max_message_size: usize,
+    max_concurrent_requests: usize,
+    #[cfg(feature = "websocket")]
+    notifier: Notifier,
+}
+
+impl RpcServer {
+    pub fn builder() -> RpcServerBuilder {
+        RpcServerBuilder::default()
+    }
+
+    /// 待ち受けるアドレス
+    pub fn listen_addrs(&self) -> &[ListenAddr] {
+        &self.listen_addrs
+    }
+
+    /// WebSocket のクライアントへ通知を送るためのハンドル
+    #[cfg(feature = "websocket")]
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// ソケットを作成し、SIGINT / SIGTERM を受けるまで接続を受け付ける
+    pub async fn serve(self) -> io::Result<()> {
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        self.serve_with_shutdown(async move {
+            tokio::select! {
+                _ = sigint.recv() => log::info!("SIGINT を受信"),
+                _ = sigterm.recv() => log::info!("SIGTERM を受信"),
+            }
+        })
+        .await
+    }
+
+    /// すべてのアドレスで待ち受け、`shutdown` が完了するまで接続を受け付ける
+    ///
+    /// 停止時は新しい接続とリクエストの受け付けをやめ、処理中のリクエストを
+    /// `shutdown_timeout` まで待ってからソケットファイルを削除する
+    pub async fn serve_with_shutdown(self, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+        let listeners = bind_all(&self.listen_addrs).await?;
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let (conn_tx, mut conn_rx) = mpsc::channel::<Connection>(64);
+
+        // リスナーごとに受け付けタスクを起動し、接続をこのループに集める
+        let mut acceptors = JoinSet::new();
+        for listener in listeners {
+            let conn_tx = conn_tx.clone();
+            let mut shutdown_rx = shutdown_rx.clone();
+            acceptors.spawn(async move {
+                loop {
+                    tokio::select! {
+                        result = listener.accept() => match result {
+                            Ok(conn) => {
+                                if conn_tx.send(conn).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) => {
+                                log::warn!("Connection failed: {}", e);
+                            }
+                        },
+                        _ = shutdown_rx.changed() => break,
+                    }
+                }
+                listener
+            });
+        }
+        drop(conn_tx);
+
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                Some(conn) = conn_rx.recv() => {
+                    log::info!("New client connected!");
+                    // 各接続のタスクからメソッドテーブルを共有する
+                    let methods = Arc::clone(&self.methods);
+                    let shutdown_rx = shutdown_rx.clone();
+                    match conn {
+                        Connection::Stream(reader, writer) => {
+                            connections.spawn(handle_connection(reader, writer, methods, self.framing, self.max_message_size, self.max_concurrent_requests, shutdown_rx));
+                        }
+                        #[cfg(feature = "http")]
+                        Connection::Http(stream) => {
+                            connections.spawn(crate::http::handle_http_connection(stream, methods, self.max_message_size, shutdown_rx));
+                        }
+                        #[cfg(feature = "websocket")]
+                        Connection::WebSocket(stream) => {
+                            let notifier = self.notifier.clone();
+                            connections.spawn(crate::ws::handle_ws_connection(stream, methods, notifier, self.max_message_size, self.max_concurrent_requests, shutdown_rx));
+                        }
+                    }
+                }
+                // 終了した接続のタスクを回収する
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                _ = &mut shutdown => break,
+            }
+        }
+
+        log::info!("シャットダウン中: 処理中の接続 {} 件", connections.len());
+        let _ = shutdown_tx.send(true);
+
+        // 新しい接続の受け付けをやめる
+        let mut listeners = Vec::new();
+        while let Some(result) = acceptors.join_next().await {
+            if let Ok(listener) = result {
+                listeners.push(listener);
+            }
+        }
+
+        let drain = async { while connections.join_next().await.is_some() {} };
+        if tokio::time::timeout(self.shutdown_timeout, drain)
+            .await
+            .is_err()
+        {
+            log::info!("タイムアウトのため残りの接続を切断");
+            connections.shutdown().await;
+        }
+
+        // 1つ失敗しても残りのソケットファイルは片付ける
+        let mut result = Ok(());
+        for listener in listeners {
+            if let Err(e) = listener.close() {
+                log::warn!("リスナーを閉じられませんでした: {}", e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        log::info!("シャットダウン完了");
+        result
+    }
+}
+
+/// すべてのアドレスでバインドする。途中で失敗した場合は作成済みのソケットを片付ける
+async fn bind_all(addrs: &[ListenAddr]) -> io::Result<Vec<Listener>> {
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        match Listener::bind(addr).await {
+            Ok(listener) => {
+                log::info!("待ち受け開始: {}", addr);
+                listeners.push(listener);
+            }
+            Err(e) => {
+                for listener in listeners {
+                    let _ = listener.close();
+                }
+                return Err(io::Error::new(e.kind(), format!("{}: {}", addr, e)));
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+/// [`RpcServer`] のビルダー
+pub struct RpcServerBuilder {
+    listen_addrs: Vec<ListenAddr>,
+    methods: MethodTable,
+    request_timeout: Duration,
+    shutdown_timeout: Duration,
+    framing: Framing,
+    max_message_size: usize,
+    max_concurrent_requests: usize,
+    #[cfg(feature = "websocket")]
+    notifier: Notifier,
+}
+
+impl Default for RpcServerBuilder {
+    fn default() -> Self {
+        RpcServerBuilder {
+            listen_addrs: Vec::new(),
+            methods: MethodTable::new(),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            framing: Framing::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            #[cfg(feature = "websocket")]
+            notifier: Notifier::default(),
+        }
+    }
+}
+
+impl RpcServerBuilder {
+    /// Unix Domain Socket で待ち受ける
+    pub fn socket_path(self, path: impl Into<PathBuf>) -> Self {
+        self.listen(ListenAddr::Unix(path.into()))
+    }
+
+    /// 待ち受けるアドレスを追加する。複数指定するとすべてで同時に待ち受ける
+    pub fn listen(mut self, addr: ListenAddr) -> Self {
+        self.listen_addrs.push(addr);
+        self
+    }
+
+    /// メソッドを登録する
+    ///
+    /// # Panics
+    ///
+    /// 同じ名前のメソッドが既に登録されている場合（以下の登録メソッドも同じ）
+    pub fn method(mut self, name: impl Into<String>, method: impl Into<RpcMethod>) -> Self {
+        service::insert(&mut self.methods, name.into(), method.into());
+        self
+    }
+
+    /// メソッドテーブルの内容をまとめて登録する
+    pub fn methods(mut self, methods: MethodTable) -> Self {
+        service::mount(&mut self.methods, "", methods);
+        self
+    }
+
+    /// `#[rpc_service]` で宣言したサービスのメソッドをまとめて登録する
+    pub fn service(self, service: impl RpcService) -> Self {
+        self.methods(service.method_table())
+    }
+
+    /// サービスのメソッドを名前空間 `prefix` に登録する（`math` なら `math.floor` になる）
+    pub fn mount(mut self, prefix: &str, service: impl RpcService) -> Self {
+        service::mount(&mut self.methods, prefix, service.method_table());
+        self
+    }
+
+    /// メソッドの実行時間の上限。[`RpcMethod::timeout`] を設定したメソッドはそちらを優先する
+    ///
+    /// 超えた場合はハンドラのタスクを中断し、`-32810` を返す
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// シャットダウン時に処理中のリクエストを待つ時間
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Unix / TCP の接続でメッセージを区切る方法。デフォルトは接続ごとに自動判定する
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// 1メッセージの大きさの上限（バイト）
+    ///
+    /// 超えた場合は `-32600` を返して接続を閉じる（HTTP ではステータス 413 で応答する）
+    pub fn max_message_size(mut self, max_size: usize) -> Self {
+        self.max_message_size = max_size;
+        self
+    }
+
+    /// 1つの接続で同時に処理するリクエストの数（バッチは1件と数える）
+    ///
+    /// 上限に達している間は空きを待つメッセージを同じ件数まで読み込み、それを超えたら読み込みを止める
+    pub fn max_concurrent_requests(mut self, max_concurrent: usize) -> Self {
+        // 0 では何も処理できないので 1 として扱う
+        self.max_concurrent_requests = max_concurrent.max(1);
+        self
+    }
+
+    /// WebSocket のクライアントへ通知を送るためのハンドル
+    ///
+    /// ハンドラのクロージャにキャプチャさせれば、メソッドの中からも通知を送れる
+    #[cfg(feature = "websocket")]
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// アドレスが指定されていない場合は [`DEFAULT_SOCKET_PATH`] で待ち受ける
+    pub fn build(mut self) -> RpcServer {
+        if self.listen_addrs.is_empty() {
+            self.listen_addrs
+                .push(ListenAddr::Unix(PathBuf::from(DEFAULT_SOCKET_PATH)));
+        }
+        for method in self.methods.values_mut() {
+            method.default_timeout(self.request_timeout);
+        }
+        RpcServer {
+            listen_addrs: self.listen_addrs,
+            methods: Arc::new(self.methods),
+            shutdown_timeout: self.shutdown_timeout,
+            framing: self.framing,
+            max_message_size: self.max_message_size,
+            max_concurrent_requests: self.max_concurrent_requests,
+            #[cfg(feature = "websocket")]
+            notifier: self.notifier,
+        }
+    }
+
+    /// `build()` してそのまま `serve()` する
+    pub async fn serve(self) -> io::Result<()> {
+        self.build().serve().await
+    }
+}
+
+/// 1つの接続に対して、EOFまでリクエストを処理する
+///
+/// メッセージの区切り方は `framing` に従い、レスポンスも同じ区切り方で返す。
+/// リクエストは `max_concurrent` 件まで並行に処理し、終わった順にレスポンスを書き込む。
+/// `max_size` を超えるメッセージにはエラーを返して接続を閉じる。
+/// シャットダウンが通知されたら、処理中のリクエストに応答してから終了する
+async fn handle_connection(
+    read_half: BoxReader,
+    mut write_half: BoxWriter,
+    methods: Arc<MethodTable>,
+    framing: Framing,
+    max_size: usize,
+    max_concurrent: usize,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    // read_frame は途中で止めると読みかけのメッセージを失うので、select! では待たずに
+    // 専用のタスクで読んで、読み終えたメッセージだけを受け取る
+    let (frame_tx, mut frames) = mpsc::channel::<(Framing, io::Result<Frame>)>(1);
+    let reader = tokio::spawn(async move {
+        let mut reader = FrameReader::new(read_half, framing, max_size);
+        loop {
+            let frame = reader.read_frame().await;
+            let done = !matches!(frame, Ok(Frame::Message(_)));
+            if frame_tx.send((reader.framing(), frame)).await.is_err() || done {
+                break;
+            }
+        }
+    });
+
+    // 書き込みは1つのタスクにまとめる
+    let (tx, mut rx) = mpsc::channel::<(Framing, String)>(64);
+    let writer = tokio::spawn(async move {
+        while let Some((framing, json_response)) = rx.recv().await {
+            if let Err(e) = write_frame(&mut write_half, framing, json_response.as_bytes()).await {
+                log::warn!("Error sending response: {}", e);
+                break;
+            }
+            log::debug!("Response sent successfully: {}", json_response);
+        }
+        let _ = write_half.shutdown().await;
+    });
+
+    let mut in_flight = InFlight::new(max_concurrent);
+    let mut framing = framing;
+    loop {
+        tokio::select! {
+            Some((detected, read)) = frames.recv(), if !in_flight.is_full() => {
+                framing = detected;
+                match read {
+                    Ok(Frame::Closed) => {
+                        log::info!("接続終了");
+                        break;
+                    }
+                    Ok(Frame::TooLarge) => {
+                        log::warn!(
+                            "エラー: メッセージが上限 ({} バイト) を超えています",
+                            max_size
+                        );
+                        if let Ok(json_response) =
+                            serde_json::to_string(&too_large_reply(max_size))
+                        {
+                            let _ = tx.send((framing, json_response)).await;
+                        }
+                        // 残りは読まずに接続を閉じる
+                        break;
+                    }
+                    Ok(Frame::Message(frame)) => {
+                        // UTF-8 として不正なバイトは置換文字として扱う
+                        let message = String::from_utf8_lossy(&frame).into_owned();
+                        log::debug!("受信: {}", message);
+                        for json_response in in_flight.push(message) {
+                            let _ = tx.send((framing, json_response)).await;
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("エラー: {}", e);
+                        break;
+                    }
+                }
+            }
+            // 空きを待つ間もシャットダウンに応じる
+            Some(permit) = in_flight.acquire(), if in_flight.has_queued() => {
+                in_flight.spawn_next(permit, &methods, &tx, move |json| (framing, json));
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    // 受信済みのリクエストに応答し終えてから閉じる
+    reader.abort();
+    in_flight
+        .finish(&methods, &tx, move |json| (framing, json))
+        .await;
+    drop(tx);
+    let _ = writer.await;
+}
@@ -0,0 +1,69 @@
+//! `#[rpc_service]` で宣言したメソッドの集まり
+//!
+//! ```ignore
+//! pub struct Math;
+//!
+//! #[rpc_service]
+//! impl Math {
+//!     fn floor(&self, x: f64) -> i64 { .. }
+//! }
+//!
+//! // `math.floor` として登録する
+//! RpcServer::builder().mount("math", Math).serve().await?;
+//!
+//! let math = MathClient::mounted(RpcClient::connect(&addr).await?, "math");
+//! let floored = math.floor(2.5).await?;
+//! ```
+//!
+//! 別々に作ったサービスを名前空間に分けて1つのサーバーにまとめられる。
+//! 名前が重なった場合は登録した時点でパニックする
+
+use std::collections::hash_map::Entry;
+
+use crate::method::{MethodTable, RpcMethod};
+
+/// まとめて登録できるメソッドの集まり。通常は `#[rpc_service]` で実装する
+pub trait RpcService: Send + Sync + 'static {
+    /// 公開するメソッドの名前
+    const METHODS: &'static [&'static str];
+
+    /// 各メソッドを登録したメソッドテーブルを作る
+    fn method_table(self) -> MethodTable;
+}
+
+/// 名前空間とメソッド名の区切り
+pub const NAMESPACE_SEPARATOR: &str = ".";
+
+/// 名前空間 `prefix` に置いたメソッドの名前。`prefix` が空の場合は `name` のまま
+pub fn qualified_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}{}{}", prefix, NAMESPACE_SEPARATOR, name)
+    }
+}
+
+/// `methods` を名前空間 `prefix` に置いて `table` に加える
+///
+/// # Panics
+///
+/// 同じ名前のメソッドが `table` に既にある場合
+pub fn mount(table: &mut MethodTable, prefix: &str, methods: MethodTable) {
+    for (name, method) in methods {
+        insert(table, qualified_name(prefix, &name), method);
+    }
+}
+
+/// メソッドを1つ加える
+///
+/// # Panics
+///
+/// 同じ名前のメソッドが `table` に既にある場合
+pub fn insert(table: &mut MethodTable, name: String, method: RpcMethod) {
+    match table.entry(name) {
+        Entry::Occupied(entry) => panic!("method '{}' is already registered", entry.key()),
+        Entry::Vacant(entry) => {
+            entry.insert(method);
+        }
+    }
+}
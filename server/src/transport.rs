@@ -0,0 +1,208 @@
+use std::{
+    fmt, io,
+    os::unix::{fs::FileTypeExt, net::UnixStream as StdUnixStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[cfg(any(feature = "http", feature = "websocket"))]
+use tokio::net::TcpStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+};
+
+/// 受け付けた接続の読み込み側と書き込み側
+pub(crate) type BoxReader = Box<dyn AsyncRead + Send + Unpin>;
+pub(crate) type BoxWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// 受け付けた接続
+pub(crate) enum Connection {
+    /// 改行区切りの JSON をやり取りするストリーム
+    Stream(BoxReader, BoxWriter),
+    /// HTTP/1.1 の接続
+    #[cfg(feature = "http")]
+    Http(TcpStream),
+    /// WebSocket のハンドシェイク前の接続
+    #[cfg(feature = "websocket")]
+    WebSocket(TcpStream),
+}
+
+/// 指定できる待ち受けアドレスの形式
+pub const SUPPORTED_SCHEMES: &[&str] = &[
+    "unix://<path>",
+    "tcp://<host>:<port>",
+    #[cfg(feature = "http")]
+    "http://<host>:<port>",
+    #[cfg(feature = "websocket")]
+    "ws://<host>:<port>",
+];
+
+/// 待ち受けるアドレス
+///
+/// `unix:///tmp/rpc.sock` または `tcp://127.0.0.1:7000` の形式で指定する。
+/// `http` フィーチャーが有効な場合は `http://127.0.0.1:8080`、
+/// `websocket` フィーチャーが有効な場合は `ws://127.0.0.1:9000` も指定できる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Unix(PathBuf),
+    Tcp(String),
+    /// `POST /rpc` で受け付ける HTTP サーバー
+    #[cfg(feature = "http")]
+    Http(String),
+    /// テキストフレームで受け付ける WebSocket サーバー
+    #[cfg(feature = "websocket")]
+    WebSocket(String),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://").filter(|path| !path.is_empty()) {
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+        if let Some(addr) = s.strip_prefix("tcp://").filter(|addr| !addr.is_empty()) {
+            return Ok(ListenAddr::Tcp(addr.to_string()));
+        }
+        #[cfg(feature = "http")]
+        if let Some(addr) = s.strip_prefix("http://").filter(|addr| !addr.is_empty()) {
+            return Ok(ListenAddr::Http(addr.trim_end_matches('/').to_string()));
+        }
+        #[cfg(feature = "websocket")]
+        if let Some(addr) = s.strip_prefix("ws://").filter(|addr| !addr.is_empty()) {
+            return Ok(ListenAddr::WebSocket(
+                addr.trim_end_matches('/').to_string(),
+            ));
+        }
+        Err(format!(
+            "unsupported listen address '{}' (expected {})",
+            s,
+            SUPPORTED_SCHEMES.join(", ")
+        ))
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Unix(path) => write!(f, "unix://{}", path.display()),
+            ListenAddr::Tcp(addr) => write!(f, "tcp://{}", addr),
+            #[cfg(feature = "http")]
+            ListenAddr::Http(addr) => write!(f, "http://{}", addr),
+            #[cfg(feature = "websocket")]
+            ListenAddr::WebSocket(addr) => write!(f, "ws://{}", addr),
+        }
+    }
+}
+
+/// バインド済みのリスナー
+pub(crate) enum Listener {
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+    Tcp(TcpListener),
+    #[cfg(feature = "http")]
+    Http(TcpListener),
+    #[cfg(feature = "websocket")]
+    WebSocket(TcpListener),
+}
+
+impl Listener {
+    pub(crate) async fn bind(addr: &ListenAddr) -> io::Result<Self> {
+        match addr {
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)?;
+                Ok(Listener::Unix {
+                    listener,
+                    path: path.clone(),
+                })
+            }
+            ListenAddr::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(feature = "http")]
+            ListenAddr::Http(addr) => Ok(Listener::Http(TcpListener::bind(addr).await?)),
+            #[cfg(feature = "websocket")]
+            ListenAddr::WebSocket(addr) => Ok(Listener::WebSocket(TcpListener::bind(addr).await?)),
+        }
+    }
+
+    /// 接続を1つ受け付ける
+    pub(crate) async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Unix { listener, .. } => {
+                let (stream, _addr) = listener.accept().await?;
+                let (read_half, write_half) = stream.into_split();
+                Ok(Connection::Stream(
+                    Box::new(read_half),
+                    Box::new(write_half),
+                ))
+            }
+            Listener::Tcp(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                let (read_half, write_half) = stream.into_split();
+                Ok(Connection::Stream(
+                    Box::new(read_half),
+                    Box::new(write_half),
+                ))
+            }
+            #[cfg(feature = "http")]
+            Listener::Http(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Ok(Connection::Http(stream))
+            }
+            #[cfg(feature = "websocket")]
+            Listener::WebSocket(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Ok(Connection::WebSocket(stream))
+            }
+        }
+    }
+
+    /// リスナーを閉じ、Unix ソケットの場合はソケットファイルを削除する
+    pub(crate) fn close(self) -> io::Result<()> {
+        match self {
+            Listener::Unix { listener, path } => {
+                drop(listener);
+                std::fs::remove_file(&path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 前回の起動で残ったソケットファイルを取り除く
+///
+/// ソケット以外のファイルや、まだ接続を受け付けているソケットはエラーにする
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    // 接続できる場合は別のサーバーが動いている
+    match StdUnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another server is already listening on {}", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            log::info!("古いソケットを削除: {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,265 @@
+//! 型付きの関数をそのままメソッドとして登録する
+//!
+//! ```ignore
+//! fn nroot(n: i64, x: f64) -> f64 { .. }
+//! async fn sort(arr: Vec<String>) -> Vec<String> { .. }
+//!
+//! RpcServer::builder()
+//!     .method("nroot", RpcMethod::typed(["n", "x"], nroot))
+//!     .method("sort", RpcMethod::typed(["strArr"], sort))
+//! ```
+//!
+//! 引数は位置指定・名前指定のどちらからでも取り出し、戻り値は JSON にして返す。
+//! シグネチャと `result_type` は引数・戻り値の型から決まる
+
+use std::{future::Future, sync::Arc};
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    method::{BoxFuture, ParamType, RpcMethod},
+    rpc::{Params, RpcError},
+};
+
+/// 引数・戻り値として使える型
+///
+/// `param_type` が `Some` の型だけの関数は、シグネチャによる検査と `--help` の表示に対応する。
+/// 独自の型は `impl RpcType for Point {}` のように実装すれば使える（シグネチャは付かない）
+pub trait RpcType {
+    fn param_type() -> Option<ParamType> {
+        None
+    }
+}
+
+macro_rules! impl_rpc_type {
+    ($param_type:expr => $($ty:ty),*) => {
+        $(
+            impl RpcType for $ty {
+                fn param_type() -> Option<ParamType> {
+                    Some($param_type)
+                }
+            }
+        )*
+    };
+}
+
+impl_rpc_type!(ParamType::Int => i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_rpc_type!(ParamType::Double => f32, f64);
+impl_rpc_type!(ParamType::String => String, char);
+impl_rpc_type!(ParamType::Bool => bool);
+
+impl<T: RpcType> RpcType for Vec<T> {
+    fn param_type() -> Option<ParamType> {
+        T::param_type().map(ParamType::array_of)
+    }
+}
+
+impl<T: RpcType> RpcType for Option<T> {}
+impl RpcType for Value {}
+impl RpcType for () {}
+
+/// ハンドラの戻り値をレスポンスの `result` にする
+pub trait IntoRpcResult {
+    fn into_rpc_result(self) -> Result<Value, RpcError>;
+
+    /// レスポンスの `result_type`
+    fn result_type() -> Option<ParamType>;
+}
+
+impl<T: Serialize + RpcType> IntoRpcResult for T {
+    fn into_rpc_result(self) -> Result<Value, RpcError> {
+        serde_json::to_value(self).map_err(|e| RpcError::internal_error(e.to_string()))
+    }
+
+    fn result_type() -> Option<ParamType> {
+        T::param_type()
+    }
+}
+
+impl<T: Serialize + RpcType> IntoRpcResult for Result<T, RpcError> {
+    fn into_rpc_result(self) -> Result<Value, RpcError> {
+        self.and_then(IntoRpcResult::into_rpc_result)
+    }
+
+    fn result_type() -> Option<ParamType> {
+        T::param_type()
+    }
+}
+
+/// パラメータから取り出す引数の組
+pub trait FromParams: Sized {
+    /// 引数の数
+    const ARITY: usize;
+
+    /// `names[i]` を i 番目の引数の名前として取り出す
+    fn from_params(params: Params, names: &[&'static str]) -> Result<Self, RpcError>;
+
+    /// 引数の名前と型。型の分からない引数がある場合は `None`
+    fn signature(names: &[&'static str]) -> Option<Vec<(&'static str, ParamType)>>;
+}
+
+macro_rules! impl_from_params {
+    ($arity:expr; $($arg:ident: $index:tt),*) => {
+        impl<$($arg: DeserializeOwned + RpcType),*> FromParams for ($($arg,)*) {
+            const ARITY: usize = $arity;
+
+            #[allow(unused_variables)]
+            fn from_params(params: Params, names: &[&'static str]) -> Result<Self, RpcError> {
+                check_shape(&params, names)?;
+                Ok(($(extract::<$arg>(&params, $index, names[$index])?,)*))
+            }
+
+            #[allow(unused_variables)]
+            fn signature(names: &[&'static str]) -> Option<Vec<(&'static str, ParamType)>> {
+                Some(vec![$((names[$index], $arg::param_type()?)),*])
+            }
+        }
+    };
+}
+
+impl_from_params!(0;);
+impl_from_params!(1; A: 0);
+impl_from_params!(2; A: 0, B: 1);
+impl_from_params!(3; A: 0, B: 1, C: 2);
+impl_from_params!(4; A: 0, B: 1, C: 2, D: 3);
+impl_from_params!(5; A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_from_params!(6; A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+
+/// 引数の数と名前を検査する
+fn check_shape(params: &Params, names: &[&'static str]) -> Result<(), RpcError> {
+    match params {
+        Params::ByPosition(values) if values.len() > names.len() => {
+            Err(RpcError::invalid_params(format!(
+                "Invalid params: expected {} params, got {}",
+                names.len(),
+                values.len()
+            )))
+        }
+        Params::ByName(values) => match values.keys().find(|key| !names.contains(&key.as_str())) {
+            Some(unknown) => Err(RpcError::invalid_params(format!(
+                "Invalid params: unknown param '{}'",
+                unknown
+            ))),
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// 引数を1つ取り出す。省略された引数は null として扱う（`Option` の引数は省略できる）
+fn extract<T: DeserializeOwned>(params: &Params, index: usize, name: &str) -> Result<T, RpcError> {
+    let value = params.get(index, name).cloned();
+    let missing = value.is_none();
+    serde_json::from_value(value.unwrap_or(Value::Null)).map_err(|e| {
+        let detail = if missing {
+            "missing".to_string()
+        } else {
+            e.to_string()
+        };
+        RpcError::invalid_params(format!(
+            "Invalid params: params[{}] ({}): {}",
+            index, name, detail
+        ))
+    })
+}
+
+/// 同期関数として登録するハンドラの目印
+pub struct SyncHandler;
+
+/// 非同期関数として登録するハンドラの目印
+pub struct AsyncHandler;
+
+/// 型付きの引数を取るハンドラ
+///
+/// `Marker` は同期関数と非同期関数の実装を区別するためだけに使う
+pub trait TypedHandler<Args, Marker>: Send + Sync + 'static {
+    type Output: IntoRpcResult;
+
+    fn call(&self, args: Args) -> BoxFuture<Result<Value, RpcError>>;
+}
+
+macro_rules! impl_typed_handler {
+    ($($arg:ident),*) => {
+        impl<Func, Res, $($arg),*> TypedHandler<($($arg,)*), SyncHandler> for Func
+        where
+            Func: Fn($($arg),*) -> Res + Send + Sync + 'static,
+            Res: IntoRpcResult,
+        {
+            type Output = Res;
+
+            #[allow(non_snake_case)]
+            fn call(&self, ($($arg,)*): ($($arg,)*)) -> BoxFuture<Result<Value, RpcError>> {
+                let result = self($($arg),*).into_rpc_result();
+                Box::pin(async move { result })
+            }
+        }
+
+        impl<Func, Fut, $($arg),*> TypedHandler<($($arg,)*), AsyncHandler> for Func
+        where
+            Func: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoRpcResult,
+        {
+            type Output = Fut::Output;
+
+            #[allow(non_snake_case)]
+            fn call(&self, ($($arg,)*): ($($arg,)*)) -> BoxFuture<Result<Value, RpcError>> {
+                let future = self($($arg),*);
+                Box::pin(async move { future.await.into_rpc_result() })
+            }
+        }
+    };
+}
+
+impl_typed_handler!();
+impl_typed_handler!(A);
+impl_typed_handler!(A, B);
+impl_typed_handler!(A, B, C);
+impl_typed_handler!(A, B, C, D);
+impl_typed_handler!(A, B, C, D, E);
+impl_typed_handler!(A, B, C, D, E, F);
+
+impl RpcMethod {
+    /// 型付きの関数をハンドラとして登録する
+    ///
+    /// `names` は名前指定のパラメータで使う引数の名前。
+    /// 引数と戻り値の型が分かる場合はシグネチャと `result_type` も設定する
+    ///
+    /// # Panics
+    ///
+    /// `names` の数が関数の引数の数と合わない場合
+    pub fn typed<Args, Marker, H>(names: impl IntoIterator<Item = &'static str>, handler: H) -> Self
+    where
+        Args: FromParams + Send + 'static,
+        H: TypedHandler<Args, Marker>,
+    {
+        let names = names.into_iter().collect::<Arc<[_]>>();
+        assert_eq!(
+            names.len(),
+            Args::ARITY,
+            "expected {} param names, got {:?}",
+            Args::ARITY,
+            names
+        );
+        let signature = Args::signature(&names);
+        let result_type = H::Output::result_type();
+
+        let handler = Arc::new(handler);
+        let mut method = RpcMethod::new(move |params: Params| {
+            let handler = Arc::clone(&handler);
+            let names = Arc::clone(&names);
+            async move {
+                let args = Args::from_params(params, &names)?;
+                handler.call(args).await
+            }
+        });
+        if let Some(signature) = signature {
+            method = method.params(signature);
+        }
+        if let Some(result_type) = result_type {
+            method = method.returns(result_type.to_string());
+        }
+        method
+    }
+}
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc, watch},
+};
+use tokio_tungstenite::tungstenite::{
+    Error as WsError, Message,
+    protocol::{CloseFrame, WebSocketConfig, frame::coding::CloseCode},
+};
+
+use crate::{
+    dispatch::{InFlight, too_large_reply},
+    method::MethodTable,
+    rpc::{RpcNotification, Version},
+};
+
+/// 通知を溜めておける数。遅い接続はこれを超えた分の通知を取りこぼす
+const NOTIFY_CAPACITY: usize = 256;
+
+/// WebSocket で接続中のすべてのクライアントへ通知を送る
+///
+/// ```ignore
+/// let builder = RpcServer::builder();
+/// let notifier = builder.notifier();
+/// notifier.notify("progress", json!({ "done": 3 }));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    sender: broadcast::Sender<Arc<str>>,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(NOTIFY_CAPACITY);
+        Notifier { sender }
+    }
+}
+
+impl Notifier {
+    /// JSON-RPC の通知（`id` のないリクエスト）を送る
+    ///
+    /// 届いた接続の数を返す
+    pub fn notify(&self, method: impl Into<String>, params: Value) -> usize {
+        let notification = RpcNotification {
+            jsonrpc: Version,
+            method: method.into(),
+            params,
+        };
+        match serde_json::to_string(&notification) {
+            Ok(json) => self.sender.send(Arc::from(json)).unwrap_or(0),
+            Err(e) => {
+                log::warn!("Error converting notification to JSON: {}", e);
+                0
+            }
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.sender.subscribe()
+    }
+}
+
+/// 1つの WebSocket 接続を処理する
+///
+/// テキストフレーム1つが1メッセージ（単体リクエストまたはバッチ）に対応する。
+/// リクエストは `max_concurrent` 件まで並行に処理し、終わった順にレスポンスを送る。
+/// `max_size` を超えるメッセージにはエラーを返して接続を閉じる。
+/// シャットダウンが通知されたら、処理中のリクエストに応答してから Close フレームを送る
+pub(crate) async fn handle_ws_connection(
+    stream: TcpStream,
+    methods: Arc<MethodTable>,
+    notifier: Notifier,
+    max_size: usize,
+    max_concurrent: usize,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(max_size))
+        .max_frame_size(Some(max_size));
+    let ws = match tokio_tungstenite::accept_async_with_config(stream, Some(config)).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            log::warn!("WebSocket ハンドシェイク失敗: {}", e);
+            return;
+        }
+    };
+    let (mut sink, mut frames) = ws.split();
+
+    // レスポンスとサーバーからの通知を1つの書き込みタスクにまとめる
+    let (tx, mut rx) = mpsc::channel::<Message>(64);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if let Err(e) = sink.send(message).await {
+                log::warn!("Error sending response: {}", e);
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    let mut in_flight = InFlight::new(max_concurrent);
+    let mut too_large = false;
+    let mut notifications = notifier.subscribe();
+    loop {
+        tokio::select! {
+            frame = frames.next(), if !in_flight.is_full() => {
+                let text = match frame {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(WsError::Capacity(e))) => {
+                        log::warn!("エラー: {}", e);
+                        too_large = true;
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        log::warn!("エラー: {}", e);
+                        break;
+                    }
+                };
+                log::debug!("受信: {}", text);
+                for json_response in in_flight.push(text.to_string()) {
+                    let _ = tx.send(Message::text(json_response)).await;
+                }
+            }
+            // 空きを待つ間も通知の転送とシャットダウンを止めない
+            Some(permit) = in_flight.acquire(), if in_flight.has_queued() => {
+                in_flight.spawn_next(permit, &methods, &tx, Message::text);
+            }
+            notification = notifications.recv() => match notification {
+                Ok(json) => {
+                    if tx.send(Message::text(json.as_ref())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    log::warn!("通知を {} 件取りこぼしました", skipped);
+                }
+                // `notifier` を保持しているので送信側が閉じることはない
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    // 受信済みのリクエストに応答し終えてから閉じる
+    in_flight.finish(&methods, &tx, Message::text).await;
+    if too_large {
+        if let Ok(json_response) = serde_json::to_string(&too_large_reply(max_size)) {
+            let _ = tx.send(Message::text(json_response)).await;
+        }
+        let close = CloseFrame {
+            code: CloseCode::Size,
+            reason: "message too large".into(),
+        };
+        let _ = tx.send(Message::Close(Some(close))).await;
+    }
+    drop(tx);
+    let _ = writer.await;
+    log::info!("接続終了");
+}
@@ -0,0 +1,146 @@
+//! 1つの接続の上でのリクエストの扱い
+
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use serde_json::{Value, json};
+use server::{ListenAddr, Params, RpcMethod, RpcServer};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        UnixStream,
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    time::sleep,
+};
+
+/// 引数のミリ秒だけ待ってから `"slept"` を返す `sleep` メソッドを持つサーバーに接続する
+async fn connect(
+    name: &str,
+    max_concurrent: usize,
+) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+    let path = PathBuf::from(format!(
+        "{}/rpc-test-{}-{}.sock",
+        std::env::temp_dir().display(),
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let server = RpcServer::builder()
+        .listen(ListenAddr::Unix(path.clone()))
+        .max_concurrent_requests(max_concurrent)
+        .method(
+            "sleep",
+            RpcMethod::new(|params| async move {
+                let ms = match params {
+                    Params::ByPosition(args) => args.first().and_then(Value::as_u64),
+                    Params::ByName(_) => None,
+                };
+                sleep(Duration::from_millis(ms.unwrap_or(0))).await;
+                Ok(json!("slept"))
+            }),
+        )
+        .build();
+    tokio::spawn(server.serve());
+
+    for _ in 0..100 {
+        if let Ok(stream) = UnixStream::connect(&path).await {
+            let (read_half, write_half) = stream.into_split();
+            return (BufReader::new(read_half).lines(), write_half);
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("server did not start");
+}
+
+/// `count` 個のレスポンスを読み、id ごとにまとめる
+async fn read_replies(
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    count: usize,
+) -> HashMap<i64, Value> {
+    let mut replies = HashMap::new();
+    while replies.len() < count {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .expect("timed out waiting for a reply")
+            .unwrap()
+            .expect("connection closed");
+        let reply: Value = serde_json::from_str(&line).unwrap();
+        let id = reply["id"].as_i64().unwrap_or_else(|| panic!("{}", reply));
+        replies.insert(id, reply);
+    }
+    replies
+}
+
+fn sleep_request(id: i64, ms: u64) -> String {
+    format!(
+        "{}\n",
+        json!({"jsonrpc": "2.0", "method": "sleep", "params": [ms], "id": id})
+    )
+}
+
+#[tokio::test]
+async fn frame_split_across_writes_survives_a_freed_slot() {
+    let (mut lines, mut writer) = connect("split", 2).await;
+
+    // 2つを実行中にし、3つ目を待たせる
+    for id in 1..=3 {
+        writer
+            .write_all(sleep_request(id, 200).as_bytes())
+            .await
+            .unwrap();
+    }
+    // 4つ目の途中で空きができる
+    let request = sleep_request(4, 0);
+    let (head, tail) = request.split_at(request.len() / 2);
+    writer.write_all(head.as_bytes()).await.unwrap();
+    sleep(Duration::from_millis(400)).await;
+    writer.write_all(tail.as_bytes()).await.unwrap();
+
+    let replies = read_replies(&mut lines, 4).await;
+    for id in 1..=4 {
+        assert_eq!(replies[&id]["result"], "slept", "{}", replies[&id]);
+    }
+}
+
+fn cancel_notification(id: i64) -> String {
+    format!(
+        "{}\n",
+        json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": id}})
+    )
+}
+
+#[tokio::test]
+async fn cancel_reaches_a_request_waiting_in_a_full_queue() {
+    let (mut lines, mut writer) = connect("cancel-queued", 1).await;
+
+    // 1つ目の実行中に2つ目が列を埋める
+    let messages = sleep_request(1, 200) + &sleep_request(2, 200) + &cancel_notification(2);
+    writer.write_all(messages.as_bytes()).await.unwrap();
+
+    let replies = read_replies(&mut lines, 2).await;
+    assert_eq!(replies[&1]["result"], "slept", "{}", replies[&1]);
+    assert_eq!(replies[&2]["error"]["code"], -32800, "{}", replies[&2]);
+}
+
+#[tokio::test]
+async fn cancel_applies_to_a_request_taken_from_the_queue() {
+    let (mut lines, mut writer) = connect("cancel-started", 1).await;
+
+    // 中断が届くのは列にいる間、始まる直前、実行中のいずれかになるが、どれでも中断される
+    for round in 0..20 {
+        let first = round * 2 + 1;
+        let second = first + 1;
+        let messages =
+            sleep_request(first, 0) + &sleep_request(second, 1000) + &cancel_notification(second);
+        writer.write_all(messages.as_bytes()).await.unwrap();
+
+        let replies = read_replies(&mut lines, 2).await;
+        assert_eq!(replies[&first]["result"], "slept", "{}", replies[&first]);
+        assert_eq!(
+            replies[&second]["error"]["code"], -32800,
+            "{}",
+            replies[&second]
+        );
+    }
+}